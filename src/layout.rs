@@ -0,0 +1,100 @@
+use theater::ReceiverInput;
+
+pub enum KeyAction {
+    TurnOn(ReceiverInput),
+    TurnOff,
+    VolumeUp,
+    VolumeDown,
+    ToggleMute,
+    Bright,
+    Dim,
+    Nothing,
+}
+
+pub struct KeyBinding {
+    pub image: &'static str,
+    pub action: KeyAction,
+}
+
+/// Which keys do what on a given deck.
+#[derive(Debug, Clone, Copy)]
+pub enum DeckLayout {
+    /// The 15 key deck in the theater.
+    Theater,
+    /// A smaller deck (e.g. a Mini) at the couch.
+    Couch,
+    /// The foot pedal, for hands-free volume.
+    Pedal,
+}
+
+impl DeckLayout {
+    pub fn key_binding(&self, key_index: i32) -> KeyBinding {
+        match self {
+            DeckLayout::Theater => theater_key_binding(key_index),
+            DeckLayout::Couch => couch_key_binding(key_index),
+            DeckLayout::Pedal => pedal_key_binding(key_index),
+        }
+    }
+}
+
+fn binding(image: &'static str, action: KeyAction) -> KeyBinding {
+    KeyBinding { image, action }
+}
+
+fn theater_key_binding(key_index: i32) -> KeyBinding {
+    const XBOX_KEY_IDX: i32 = 0;
+    const PLAYSTATION_KEY_IDX: i32 = 1;
+    const APPLETV_IDX: i32 = 2;
+    const MUTE_IDX: i32 = 4;
+    const VOLUP_IDX: i32 = 9;
+    const POWEROFF_IDX: i32 = 10;
+    const DIM_IDX: i32 = 11;
+    const BRIGHT_IDX: i32 = 12;
+    const VOLDOWN_IDX: i32 = 14;
+
+    match key_index {
+        XBOX_KEY_IDX => binding("xbox.png", KeyAction::TurnOn(ReceiverInput::Game)),
+        PLAYSTATION_KEY_IDX => binding("ps4.png", KeyAction::TurnOn(ReceiverInput::Dvd)),
+        APPLETV_IDX => binding("appletv.png", KeyAction::TurnOn(ReceiverInput::Mplay)),
+        BRIGHT_IDX => binding("bright.png", KeyAction::Bright),
+        DIM_IDX => binding("dim.png", KeyAction::Dim),
+        VOLUP_IDX => binding("volup.png", KeyAction::VolumeUp),
+        VOLDOWN_IDX => binding("voldown.png", KeyAction::VolumeDown),
+        MUTE_IDX => binding("volmute.png", KeyAction::ToggleMute),
+        POWEROFF_IDX => binding("poweroff.png", KeyAction::TurnOff),
+        _ => binding("blank.png", KeyAction::Nothing),
+    }
+}
+
+fn couch_key_binding(key_index: i32) -> KeyBinding {
+    const XBOX_KEY_IDX: i32 = 0;
+    const APPLETV_IDX: i32 = 1;
+    const VOLUP_IDX: i32 = 2;
+    const POWEROFF_IDX: i32 = 3;
+    const MUTE_IDX: i32 = 4;
+    const VOLDOWN_IDX: i32 = 5;
+
+    match key_index {
+        XBOX_KEY_IDX => binding("xbox.png", KeyAction::TurnOn(ReceiverInput::Game)),
+        APPLETV_IDX => binding("appletv.png", KeyAction::TurnOn(ReceiverInput::Mplay)),
+        VOLUP_IDX => binding("volup.png", KeyAction::VolumeUp),
+        VOLDOWN_IDX => binding("voldown.png", KeyAction::VolumeDown),
+        MUTE_IDX => binding("volmute.png", KeyAction::ToggleMute),
+        POWEROFF_IDX => binding("poweroff.png", KeyAction::TurnOff),
+        _ => binding("blank.png", KeyAction::Nothing),
+    }
+}
+
+// The pedal has no display, so its images are never shown.
+fn pedal_key_binding(key_index: i32) -> KeyBinding {
+    const VOLDOWN_IDX: i32 = 0;
+    const MUTE_IDX: i32 = 1;
+    const VOLUP_IDX: i32 = 2;
+
+    match key_index {
+        VOLDOWN_IDX => binding("voldown.png", KeyAction::VolumeDown),
+        MUTE_IDX => binding("volmute.png", KeyAction::ToggleMute),
+        VOLUP_IDX => binding("volup.png", KeyAction::VolumeUp),
+        _ => binding("blank.png", KeyAction::Nothing),
+    }
+}
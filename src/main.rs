@@ -1,4 +1,7 @@
-use log::info;
+mod layout;
+
+use layout::{DeckLayout, KeyAction};
+use log::{error, info, warn};
 use simplelog::{ColorChoice, ConfigBuilder, LevelFilter, TermLogger, TerminalMode};
 use std::{
     path::Path,
@@ -6,9 +9,14 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    thread,
+    time::Duration,
+};
+use streamdeck::{
+    DeckEvent, DialEventKind, IdleMode, KeyEventKind, Orientation, Rotation, StreamDeckController,
+    StreamDeckError, StreamDeckInfo, StreamDeckModel, VirtualTransport,
 };
-use streamdeck::StreamDeckController;
-use theater::{ReceiverInput, Theater};
+use theater::{DenonProtocol, DenonReceiver, EpsonProjector, Theater, Volume};
 
 struct DeviceAddresses {}
 impl DeviceAddresses {
@@ -18,6 +26,46 @@ impl DeviceAddresses {
     pub fn receiver() -> String {
         "192.168.2.198".to_string()
     }
+    // HC_RECEIVER_PROTOCOL=telnet talks to the receiver on port 23 instead of HTTP
+    pub fn receiver_protocol() -> DenonProtocol {
+        match std::env::var("HC_RECEIVER_PROTOCOL").as_deref() {
+            Ok("telnet") => DenonProtocol::Telnet,
+            _ => DenonProtocol::Http,
+        }
+    }
+    // e.g. HC_RECEIVER_MAX_VOLUME=70 for nothing louder than -10dB
+    pub fn receiver_max_volume() -> Option<Volume> {
+        std::env::var("HC_RECEIVER_MAX_VOLUME")
+            .ok()
+            .and_then(|max_volume| max_volume.parse::<f32>().ok())
+            .map(Volume::from_absolute)
+    }
+}
+
+struct DeckSerials {}
+impl DeckSerials {
+    pub fn couch() -> Option<String> {
+        std::env::var("HC_COUCH_DECK_SERIAL").ok()
+    }
+}
+
+struct DeckMounting {}
+impl DeckMounting {
+    // e.g. HC_THEATER_DECK_ROTATION=180 for a deck mounted upside down
+    pub fn orientation(layout: DeckLayout) -> Orientation {
+        let variable = match layout {
+            DeckLayout::Theater => "HC_THEATER_DECK_ROTATION",
+            DeckLayout::Couch => "HC_COUCH_DECK_ROTATION",
+            DeckLayout::Pedal => return Orientation::default(),
+        };
+        let rotation = match std::env::var(variable).as_deref() {
+            Ok("90") => Rotation::Rotate90,
+            Ok("180") => Rotation::Rotate180,
+            Ok("270") => Rotation::Rotate270,
+            _ => Rotation::Rotate0,
+        };
+        Orientation::new(rotation)
+    }
 }
 
 fn main() -> anyhow::Result<(), anyhow::Error> {
@@ -38,11 +86,72 @@ fn main() -> anyhow::Result<(), anyhow::Error> {
     Ok(())
 }
 
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const BRIGHTNESS_FADE: Duration = Duration::from_millis(300);
+
 fn get_resource_path(resource_file_name: &str) -> String {
     let path = Path::new("resources").join(resource_file_name);
     path.to_string_lossy().to_string()
 }
 
+fn log_deck_error(result: Result<(), StreamDeckError>) {
+    match result {
+        Ok(()) => {}
+        Err(e) => error!("{}", e),
+    }
+}
+
+fn get_deck_layout(deck: &StreamDeckInfo) -> DeckLayout {
+    if deck.model == StreamDeckModel::Pedal {
+        return DeckLayout::Pedal;
+    }
+
+    match (&deck.serial_number, DeckSerials::couch()) {
+        (Some(serial_number), Some(couch_serial)) if *serial_number == couch_serial => {
+            DeckLayout::Couch
+        }
+        _ => DeckLayout::Theater,
+    }
+}
+
+fn open_deck(deck: &StreamDeckInfo) -> Result<StreamDeckController, StreamDeckError> {
+    match &deck.serial_number {
+        Some(serial_number) => StreamDeckController::from_serial(serial_number),
+        None => StreamDeckController::from_device_path(&deck.path),
+    }
+}
+
+// Opens every attached deck, or a virtual one when HC_VIRTUAL_DECK holds an
+// address to serve it on (e.g. 127.0.0.1:8080) for running without hardware.
+fn open_decks() -> anyhow::Result<Vec<(StreamDeckController, DeckLayout)>, anyhow::Error> {
+    if let Ok(address) = std::env::var("HC_VIRTUAL_DECK") {
+        let controller = VirtualTransport::start_controller(StreamDeckModel::Mk2, &address)?;
+        return Ok(vec![(controller, DeckLayout::Theater)]);
+    }
+
+    let mut decks = Vec::new();
+    for deck in StreamDeckController::list_devices()? {
+        let layout = get_deck_layout(&deck);
+        info!(
+            "Using the {:?} layout for {} ({})",
+            layout,
+            deck.model.descriptor().name,
+            deck.serial_number.as_deref().unwrap_or("no serial number")
+        );
+        match open_deck(&deck) {
+            Ok(controller) => decks.push((controller, layout)),
+            // the other decks are still worth running
+            Err(e) => error!(
+                "Skipping {} ({}): {}",
+                deck.model.descriptor().name,
+                deck.serial_number.as_deref().unwrap_or("no serial number"),
+                e
+            ),
+        }
+    }
+    Ok(decks)
+}
+
 #[allow(dead_code)]
 fn do_main_loop() -> anyhow::Result<(), anyhow::Error> {
     let running = Arc::new(AtomicBool::new(true));
@@ -51,81 +160,111 @@ fn do_main_loop() -> anyhow::Result<(), anyhow::Error> {
         r.store(false, Ordering::SeqCst);
     })?;
 
-    info!("Initializing the deck");
+    info!("Initializing the decks");
 
-    // Create the StreamDeckController from the vendor/product id
-    let controller = StreamDeckController::new().unwrap();
-    let theater = Theater::new(&DeviceAddresses::receiver(), &DeviceAddresses::projector());
+    let decks = open_decks()?;
+    if decks.is_empty() {
+        anyhow::bail!("no Stream Decks found");
+    }
 
-    // set default brightness
-    controller.set_brightness(5);
+    let receiver_protocol = DeviceAddresses::receiver_protocol();
+    let mut receiver =
+        DenonReceiver::with_protocol(&DeviceAddresses::receiver(), receiver_protocol);
+    if let Some(max_volume) = DeviceAddresses::receiver_max_volume() {
+        receiver.set_max_volume(max_volume);
+    }
+    let mut theater =
+        Theater::with_devices(receiver, EpsonProjector::new(&DeviceAddresses::projector()));
+    // the listener shares the telnet connection; over HTTP it would hold a
+    // second connection open that nothing else could use
+    if receiver_protocol == DenonProtocol::Telnet {
+        theater.start_receiver_status_listener();
+    }
+    let theater = Arc::new(theater);
 
-    const XBOX_KEY_IDX: i32 = 0;
-    const PLAYSTATION_KEY_IDX: i32 = 1;
-    const APPLETV_IDX: i32 = 2;
-    const MUTE_IDX: i32 = 4;
-    const VOLUP_IDX: i32 = 9;
-    const POWEROFF_IDX: i32 = 10;
-    const DIM_IDX: i32 = 11;
-    const BRIGHT_IDX: i32 = 12;
-    const VOLDOWN_IDX: i32 = 14;
+    let mut deck_threads = Vec::new();
+    for (controller, layout) in decks {
+        let theater = theater.clone();
+        let running = running.clone();
+        // each deck reports its own failure, rather than waiting for the
+        // decks before it to be joined
+        deck_threads.push(thread::spawn(move || {
+            if let Err(e) = run_deck(&controller, layout, &theater, &running) {
+                error!("The {:?} deck stopped: {}", layout, e);
+            }
+        }));
+    }
 
-    // Load the images into the streamdeck
-    for key_index in 0..controller.get_num_keys() {
-        // info!("setting image for key_index: {}", key_index);
-        match key_index {
-            XBOX_KEY_IDX => controller.set_image(key_index, &get_resource_path("xbox.png")),
-            PLAYSTATION_KEY_IDX => controller.set_image(key_index, &get_resource_path("ps4.png")),
-            APPLETV_IDX => controller.set_image(key_index, &get_resource_path("appletv.png")),
-            BRIGHT_IDX => controller.set_image(key_index, &get_resource_path("bright.png")),
-            DIM_IDX => controller.set_image(key_index, &get_resource_path("dim.png")),
-            VOLUP_IDX => controller.set_image(key_index, &get_resource_path("volup.png")),
-            VOLDOWN_IDX => controller.set_image(key_index, &get_resource_path("voldown.png")),
-            MUTE_IDX => controller.set_image(key_index, &get_resource_path("volmute.png")),
-            POWEROFF_IDX => controller.set_image(key_index, &get_resource_path("poweroff.png")),
-            _ => controller.set_image(key_index, &get_resource_path("blank.png")),
+    for deck_thread in deck_threads {
+        if deck_thread.join().is_err() {
+            warn!("a deck thread panicked");
         }
     }
 
-    let mut last_key_states = controller.create_keystates_buf();
+    Ok(())
+}
+
+fn run_deck(
+    controller: &StreamDeckController,
+    layout: DeckLayout,
+    theater: &Theater,
+    running: &AtomicBool,
+) -> anyhow::Result<(), anyhow::Error> {
+    controller.set_orientation(DeckMounting::orientation(layout));
+
+    // set default brightness, and turn the deck off overnight.  The pedal has
+    // nothing to turn off, and must never swallow a press.
+    controller.set_brightness(5)?;
+    if !matches!(layout, DeckLayout::Pedal) {
+        controller.set_idle_timeout(Some(IDLE_TIMEOUT), IdleMode::Blank)?;
+    }
+
+    // Load the images into the streamdeck
+    for key_index in 0..controller.get_num_keys() {
+        let key_binding = layout.key_binding(key_index);
+        log_deck_error(controller.set_image(key_index, &get_resource_path(key_binding.image)));
+    }
 
     info!("Starting the keypress loop");
     while running.load(Ordering::SeqCst) {
-        // check hid_device read timeout to get the state of the keys / check for button presses
-
-        let mut keys_pressed: Vec<i32> = vec![];
-        let mut keys_released: Vec<i32> = vec![];
-
-        let keystates = controller.get_keystates();
-        for idx in 1..controller.get_num_keys() + 1 {
-            let idx_usize = usize::try_from(idx).unwrap();
-            if keystates[idx_usize] != last_key_states[idx_usize] {
-                let converted_keypress: i32 = controller.convert_key_index(idx - 1);
-                if keystates[idx_usize] > 0 {
-                    info!("raw key pressed: {}", idx_usize - 1);
-                    info!("converted key pressed: {}", converted_keypress);
-                    keys_pressed.push(converted_keypress);
-                } else {
-                    keys_released.push(converted_keypress);
+        // wait for key events / check for button presses
+        let events = controller.read_events()?;
+        let keys_pressed = events.iter().filter_map(|event| match event {
+            DeckEvent::Key(key_event) if key_event.kind == KeyEventKind::Down => {
+                Some(key_event.key)
+            }
+            _ => None,
+        });
+
+        // any dial on a Stream Deck + adjusts the volume
+        let dial_events = events.iter().filter_map(|event| match event {
+            DeckEvent::Dial(dial_event) => Some(dial_event.kind),
+            _ => None,
+        });
+        for dial_event_kind in dial_events {
+            match dial_event_kind {
+                DialEventKind::Rotate(ticks) => {
+                    theater.set_volume(theater.get_volume().add_db(ticks as f32))
                 }
+                DialEventKind::Down => theater.toggle_mute(),
+                DialEventKind::Up => {}
             }
         }
-        last_key_states = keystates;
-
-        for keyidx in keys_pressed.iter() {
-            info!("processing key pressed: {}", *keyidx);
-
-            match *keyidx {
-                XBOX_KEY_IDX => theater.turn_on(ReceiverInput::Game),
-                PLAYSTATION_KEY_IDX => theater.turn_on(ReceiverInput::Dvd),
-                APPLETV_IDX => theater.turn_on(ReceiverInput::Mplay),
-                BRIGHT_IDX => controller.set_brightness(75),
-                DIM_IDX => controller.set_brightness(5),
-                VOLUP_IDX => theater.set_volume(theater.get_volume() + 5),
-                VOLDOWN_IDX => theater.set_volume(theater.get_volume() - 5),
-                MUTE_IDX => theater.toggle_mute(),
-                POWEROFF_IDX => theater.turn_off(),
-                _ => {}
+
+        for keyidx in keys_pressed {
+            info!("processing key pressed: {}", keyidx);
+
+            match layout.key_binding(keyidx).action {
+                KeyAction::TurnOn(input) => theater.turn_on(input),
+                KeyAction::Bright => {
+                    log_deck_error(controller.fade_brightness(75, BRIGHTNESS_FADE))
+                }
+                KeyAction::Dim => log_deck_error(controller.fade_brightness(5, BRIGHTNESS_FADE)),
+                KeyAction::VolumeUp => theater.set_volume(theater.get_volume().add_db(5.0)),
+                KeyAction::VolumeDown => theater.set_volume(theater.get_volume().add_db(-5.0)),
+                KeyAction::ToggleMute => theater.toggle_mute(),
+                KeyAction::TurnOff => theater.turn_off(),
+                KeyAction::Nothing => {}
             }
         }
     }
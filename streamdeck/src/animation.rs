@@ -0,0 +1,232 @@
+use log::warn;
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::imageops::FilterType;
+use image::{AnimationDecoder, DynamicImage, Frame};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{StreamDeckController, StreamDeckError};
+
+pub struct AnimationFrame {
+    pub image: DynamicImage,
+    pub delay: Duration,
+}
+
+/// A looping sequence of key images, e.g. decoded from a GIF or APNG.
+pub struct Animation {
+    frames: Vec<AnimationFrame>,
+}
+
+impl Animation {
+    // Some encoders write a 0ms delay and expect viewers to pick something sane.
+    const MIN_FRAME_DELAY_MS: u64 = 20;
+
+    pub fn new(frames: Vec<AnimationFrame>) -> Animation {
+        Animation { frames }
+    }
+
+    /// Decodes every frame of a GIF or APNG file.  A still PNG becomes a
+    /// single frame animation.
+    pub fn open(image_file_path: &str) -> Result<Animation, StreamDeckError> {
+        let reader =
+            BufReader::new(File::open(image_file_path).map_err(image::ImageError::IoError)?);
+
+        let frames = match image::ImageFormat::from_path(image_file_path)? {
+            image::ImageFormat::Gif => GifDecoder::new(reader)?.into_frames().collect_frames()?,
+            image::ImageFormat::Png => {
+                let decoder = PngDecoder::new(reader)?;
+                if decoder.is_apng() {
+                    decoder.apng().into_frames().collect_frames()?
+                } else {
+                    let image = DynamicImage::from_decoder(decoder)?;
+                    vec![Frame::new(image.to_rgba8())]
+                }
+            }
+            _ => vec![Frame::new(image::open(image_file_path)?.to_rgba8())],
+        };
+
+        Ok(Animation::new(
+            frames
+                .into_iter()
+                .map(|frame| {
+                    let (numer, denom) = frame.delay().numer_denom_ms();
+                    let delay_ms =
+                        (u64::from(numer) / u64::from(denom.max(1))).max(Self::MIN_FRAME_DELAY_MS);
+                    AnimationFrame {
+                        image: DynamicImage::ImageRgba8(frame.into_buffer()),
+                        delay: Duration::from_millis(delay_ms),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn resized(self, icon_size: u32) -> Animation {
+        Animation::new(
+            self.frames
+                .into_iter()
+                .map(|frame| AnimationFrame {
+                    image: frame
+                        .image
+                        .resize(icon_size, icon_size, FilterType::Triangle),
+                    delay: frame.delay,
+                })
+                .collect(),
+        )
+    }
+}
+
+struct RunningAnimation {
+    animation: Animation,
+    frame_index: usize,
+    next_frame_at: Instant,
+}
+
+struct SchedulerState {
+    is_running: bool,
+    animations: HashMap<i32, RunningAnimation>,
+}
+
+/// Plays animations on keys from a background thread.
+///
+/// Frames from every animation share a single frames-per-second budget so the
+/// deck's USB link isn't saturated.  Frames that can't be sent on time are
+/// delayed rather than dropped.
+pub struct AnimationScheduler {
+    controller: Arc<StreamDeckController>,
+    shared: Arc<(Mutex<SchedulerState>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AnimationScheduler {
+    pub fn new(
+        controller: Arc<StreamDeckController>,
+        max_frames_per_second: u32,
+    ) -> AnimationScheduler {
+        let shared = Arc::new((
+            Mutex::new(SchedulerState {
+                is_running: true,
+                animations: HashMap::new(),
+            }),
+            Condvar::new(),
+        ));
+        let min_frame_interval = Duration::from_secs(1) / max_frames_per_second.max(1);
+
+        let thread_controller = controller.clone();
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || {
+            run_scheduler(&thread_controller, &thread_shared, min_frame_interval)
+        });
+
+        AnimationScheduler {
+            controller,
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Starts (or replaces) the animation playing on a key.
+    pub fn start(&self, key_index: i32, animation: Animation) -> Result<(), StreamDeckError> {
+        if key_index < 0 || key_index >= self.controller.get_num_keys() {
+            return Err(StreamDeckError::InvalidKey(key_index));
+        }
+        if animation.num_frames() == 0 || !self.controller.get_descriptor().has_display {
+            return Ok(());
+        }
+
+        let animation = animation.resized(self.controller.get_descriptor().icon_size);
+        let (state, condvar) = &*self.shared;
+        state.lock().unwrap().animations.insert(
+            key_index,
+            RunningAnimation {
+                animation,
+                frame_index: 0,
+                next_frame_at: Instant::now(),
+            },
+        );
+        condvar.notify_one();
+        Ok(())
+    }
+
+    /// Stops the animation on a key, leaving its current frame displayed.
+    pub fn stop(&self, key_index: i32) {
+        let (state, condvar) = &*self.shared;
+        state.lock().unwrap().animations.remove(&key_index);
+        condvar.notify_one();
+    }
+
+    pub fn is_animating(&self, key_index: i32) -> bool {
+        let (state, _) = &*self.shared;
+        state.lock().unwrap().animations.contains_key(&key_index)
+    }
+}
+
+impl Drop for AnimationScheduler {
+    fn drop(&mut self) {
+        let (state, condvar) = &*self.shared;
+        state.lock().unwrap().is_running = false;
+        condvar.notify_one();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_scheduler(
+    controller: &StreamDeckController,
+    shared: &(Mutex<SchedulerState>, Condvar),
+    min_frame_interval: Duration,
+) {
+    let (state_mutex, condvar) = shared;
+    let mut last_frame_sent_at: Option<Instant> = None;
+
+    let mut state = state_mutex.lock().unwrap();
+    while state.is_running {
+        let next = state
+            .animations
+            .iter()
+            .min_by_key(|(_, running)| running.next_frame_at)
+            .map(|(key_index, running)| (*key_index, running.next_frame_at));
+
+        let Some((key_index, next_frame_at)) = next else {
+            state = condvar.wait(state).unwrap();
+            continue;
+        };
+
+        let due_at = match last_frame_sent_at {
+            Some(last_frame_sent_at) => next_frame_at.max(last_frame_sent_at + min_frame_interval),
+            None => next_frame_at,
+        };
+        let now = Instant::now();
+        if due_at > now {
+            state = condvar.wait_timeout(state, due_at - now).unwrap().0;
+            continue;
+        }
+
+        let running = state.animations.get_mut(&key_index).unwrap();
+        let frame = &running.animation.frames[running.frame_index];
+        let image = frame.image.clone();
+        running.next_frame_at = now + frame.delay;
+        running.frame_index = (running.frame_index + 1) % running.animation.frames.len();
+
+        // don't hold the lock across the (slow) usb write
+        drop(state);
+        match controller.set_image_exact(key_index, image) {
+            Ok(()) | Err(StreamDeckError::Disconnected) => {}
+            Err(e) => warn!("failed to draw animation frame on key {}: {}", key_index, e),
+        }
+        last_frame_sent_at = Some(Instant::now());
+        state = state_mutex.lock().unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use log::warn;
+
+use futures_core::Stream;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::{DeckEvent, StreamDeckController, StreamDeckError};
+
+type WriteJob = Box<dyn FnOnce(&StreamDeckController) + Send>;
+
+/// Deck events as they arrive.  Ends after the first read error, or once the
+/// `AsyncStreamDeck` is shut down.
+pub struct DeckEventStream {
+    events: mpsc::UnboundedReceiver<Result<DeckEvent, StreamDeckError>>,
+}
+
+impl DeckEventStream {
+    pub async fn next(&mut self) -> Option<Result<DeckEvent, StreamDeckError>> {
+        self.events.recv().await
+    }
+}
+
+impl Stream for DeckEventStream {
+    type Item = Result<DeckEvent, StreamDeckError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// Runs a `StreamDeckController` on tokio's blocking thread pool.
+///
+/// A reader task polls the deck and forwards events to a `DeckEventStream`,
+/// and a writer task works through queued image and brightness changes in
+/// order, so neither waits on the other's caller.
+pub struct AsyncStreamDeck {
+    controller: Arc<StreamDeckController>,
+    is_running: Arc<AtomicBool>,
+    write_jobs: Option<mpsc::UnboundedSender<WriteJob>>,
+    reader: Option<JoinHandle<()>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl AsyncStreamDeck {
+    // Short enough that queued writes and shutdown don't wait on a read for long.
+    const READ_TIMEOUT_MS: u64 = 50;
+
+    /// Starts the reader and writer tasks.  Must be called from within a
+    /// tokio runtime.
+    pub fn new(controller: Arc<StreamDeckController>) -> (AsyncStreamDeck, DeckEventStream) {
+        let is_running = Arc::new(AtomicBool::new(true));
+
+        let (event_sender, events) = mpsc::unbounded_channel();
+        let reader_controller = controller.clone();
+        let reader_is_running = is_running.clone();
+        let reader = tokio::task::spawn_blocking(move || {
+            run_reader(&reader_controller, &reader_is_running, &event_sender)
+        });
+
+        let (write_jobs, mut write_job_receiver) = mpsc::unbounded_channel::<WriteJob>();
+        let writer_controller = controller.clone();
+        let writer = tokio::task::spawn_blocking(move || {
+            while let Some(write_job) = write_job_receiver.blocking_recv() {
+                write_job(&writer_controller);
+            }
+        });
+
+        (
+            AsyncStreamDeck {
+                controller,
+                is_running,
+                write_jobs: Some(write_jobs),
+                reader: Some(reader),
+                writer: Some(writer),
+            },
+            DeckEventStream { events },
+        )
+    }
+
+    pub fn controller(&self) -> &Arc<StreamDeckController> {
+        &self.controller
+    }
+
+    /// Queues a call against the deck without waiting for it.  Errors are
+    /// logged.
+    pub fn queue(
+        &self,
+        write: impl FnOnce(&StreamDeckController) -> Result<(), StreamDeckError> + Send + 'static,
+    ) {
+        self.send_job(Box::new(move |controller| {
+            if let Err(e) = write(controller) {
+                warn!("queued stream deck write failed: {}", e);
+            }
+        }));
+    }
+
+    /// Queues a call against the deck and waits for it to run.
+    pub async fn write<T: Send + 'static>(
+        &self,
+        write: impl FnOnce(&StreamDeckController) -> Result<T, StreamDeckError> + Send + 'static,
+    ) -> Result<T, StreamDeckError> {
+        let (result_sender, result) = oneshot::channel();
+        self.send_job(Box::new(move |controller| {
+            let _ = result_sender.send(write(controller));
+        }));
+        result.await.unwrap_or(Err(StreamDeckError::Disconnected))
+    }
+
+    pub fn set_image(&self, key_index: i32, image_file_path: &str) {
+        let image_file_path = image_file_path.to_string();
+        self.queue(move |controller| controller.set_image(key_index, &image_file_path));
+    }
+
+    pub fn set_brightness(&self, percentage: i32) {
+        self.queue(move |controller| controller.set_brightness(percentage));
+    }
+
+    pub fn fade_brightness(&self, percentage: i32, duration: Duration) {
+        self.queue(move |controller| controller.fade_brightness(percentage, duration));
+    }
+
+    /// Stops reading, finishes any queued writes and waits for both tasks.
+    pub async fn shutdown(mut self) {
+        self.is_running.store(false, Ordering::SeqCst);
+        self.write_jobs = None;
+
+        for task in [self.reader.take(), self.writer.take()]
+            .into_iter()
+            .flatten()
+        {
+            let _ = task.await;
+        }
+    }
+
+    fn send_job(&self, write_job: WriteJob) {
+        if let Some(write_jobs) = &self.write_jobs {
+            // only fails once the writer is gone, which drops the job's result sender too
+            let _ = write_jobs.send(write_job);
+        }
+    }
+}
+
+impl Drop for AsyncStreamDeck {
+    fn drop(&mut self) {
+        // the tasks wind down on their own once they notice
+        self.is_running.store(false, Ordering::SeqCst);
+    }
+}
+
+fn run_reader(
+    controller: &StreamDeckController,
+    is_running: &AtomicBool,
+    event_sender: &mpsc::UnboundedSender<Result<DeckEvent, StreamDeckError>>,
+) {
+    let timeout = Duration::from_millis(AsyncStreamDeck::READ_TIMEOUT_MS);
+
+    while is_running.load(Ordering::SeqCst) && !event_sender.is_closed() {
+        match controller.read_events_timeout(timeout) {
+            Ok(events) => {
+                for event in events {
+                    let _ = event_sender.send(Ok(event));
+                }
+            }
+            Err(e) => {
+                let _ = event_sender.send(Err(e));
+                return;
+            }
+        }
+    }
+}
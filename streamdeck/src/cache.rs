@@ -0,0 +1,78 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::StreamDeckError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ResizeMode {
+    /// Keep the aspect ratio and fit within the size.
+    Fit,
+    /// Keep the aspect ratio and crop to fill the size.
+    Fill,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct CacheKey {
+    path: String,
+    width: u32,
+    height: u32,
+    resize_mode: ResizeMode,
+}
+
+// Decoded and resized images by file path and size, so redrawing a key from
+// the same file doesn't go back to disk.  Entries are never evicted; a deck
+// only ever shows a handful of distinct images.
+pub(crate) struct ImageCache {
+    images: HashMap<CacheKey, DynamicImage>,
+}
+
+impl ImageCache {
+    pub fn new() -> ImageCache {
+        ImageCache {
+            images: HashMap::new(),
+        }
+    }
+
+    pub fn load(
+        &mut self,
+        image_file_path: &str,
+        width: u32,
+        height: u32,
+        resize_mode: ResizeMode,
+    ) -> Result<DynamicImage, StreamDeckError> {
+        let key = CacheKey {
+            path: image_file_path.to_string(),
+            width,
+            height,
+            resize_mode,
+        };
+        if let Some(image) = self.images.get(&key) {
+            return Ok(image.clone());
+        }
+
+        let img = image::open(image_file_path)?;
+        let resized_image = match resize_mode {
+            ResizeMode::Fit => img.resize(width, height, FilterType::Triangle),
+            ResizeMode::Fill => img.resize_to_fill(width, height, FilterType::Triangle),
+        };
+        self.images.insert(key, resized_image.clone());
+        Ok(resized_image)
+    }
+
+    pub fn clear(&mut self) {
+        self.images.clear();
+    }
+}
+
+/// Hashes the pixels of an image, to tell whether a key already shows it.
+pub(crate) fn hash_image(image: &DynamicImage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image.width().hash(&mut hasher);
+    image.height().hash(&mut hasher);
+    image.color().hash(&mut hasher);
+    image.as_bytes().hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,112 @@
+use hidapi::HidError;
+use image::ImageError;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result};
+
+#[derive(Debug)]
+pub enum StreamDeckError {
+    Hid(HidError),
+    Image(ImageError),
+    Io(std::io::Error),
+    Font(String),
+    InvalidKey(i32),
+    InvalidDial(i32),
+    InvalidKeyGap(u32),
+    InvalidTouchStripArea {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Disconnected,
+    NotFound(String),
+    UnsupportedDevice(String),
+    NoDisplay(&'static str),
+    NoTouchStrip(&'static str),
+    /// The encoded image needs more pages than the report header can number.
+    ImageTooLarge,
+}
+
+impl Display for StreamDeckError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            StreamDeckError::Hid(e) => write!(f, "stream deck hid error: {}", e),
+            StreamDeckError::Image(e) => write!(f, "stream deck image error: {}", e),
+            StreamDeckError::Io(e) => write!(f, "stream deck io error: {}", e),
+            StreamDeckError::Font(message) => write!(f, "stream deck font error: {}", message),
+            StreamDeckError::InvalidKey(key_index) => {
+                write!(f, "invalid stream deck key index: {}", key_index)
+            }
+            StreamDeckError::InvalidDial(dial_index) => {
+                write!(f, "invalid stream deck dial index: {}", dial_index)
+            }
+            StreamDeckError::InvalidKeyGap(key_gap) => {
+                write!(f, "stream deck key gap too large: {}", key_gap)
+            }
+            StreamDeckError::InvalidTouchStripArea {
+                x,
+                y,
+                width,
+                height,
+            } => write!(
+                f,
+                "stream deck touch strip area out of bounds: {}x{} at ({}, {})",
+                width, height, x, y
+            ),
+            StreamDeckError::Disconnected => write!(f, "stream deck disconnected"),
+            StreamDeckError::NotFound(message) => write!(f, "stream deck not found: {}", message),
+            StreamDeckError::UnsupportedDevice(message) => {
+                write!(f, "unsupported stream deck: {}", message)
+            }
+            StreamDeckError::NoDisplay(model_name) => write!(f, "{} has no display", model_name),
+            StreamDeckError::NoTouchStrip(model_name) => {
+                write!(f, "{} has no touch strip", model_name)
+            }
+            StreamDeckError::ImageTooLarge => {
+                write!(f, "stream deck image too large to send")
+            }
+        }
+    }
+}
+
+impl Error for StreamDeckError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StreamDeckError::Hid(e) => Some(e),
+            StreamDeckError::Image(e) => Some(e),
+            StreamDeckError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<HidError> for StreamDeckError {
+    fn from(e: HidError) -> Self {
+        // hidapi only reports a lost device through its error text.
+        let is_disconnect = match &e {
+            HidError::HidApiError { message } => {
+                message.contains("disconnected") || message.contains("No such device")
+            }
+            HidError::IoError { error } => error.raw_os_error() == Some(19), // ENODEV
+            _ => false,
+        };
+
+        if is_disconnect {
+            StreamDeckError::Disconnected
+        } else {
+            StreamDeckError::Hid(e)
+        }
+    }
+}
+
+impl From<ImageError> for StreamDeckError {
+    fn from(e: ImageError) -> Self {
+        StreamDeckError::Image(e)
+    }
+}
+
+impl From<std::io::Error> for StreamDeckError {
+    fn from(e: std::io::Error) -> Self {
+        StreamDeckError::Io(e)
+    }
+}
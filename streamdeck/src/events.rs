@@ -0,0 +1,290 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEventKind {
+    Down,
+    /// The key was released.  Not sent after a `LongPress`.
+    Up,
+    /// The key has been held for the given time.  Sent once per press, in
+    /// place of the `Up`.
+    LongPress(Duration),
+    /// The key went down again shortly after the previous press.  Sent right
+    /// after the second `Down`.
+    DoubleTap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: i32,
+    pub kind: KeyEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialEventKind {
+    /// Turned by this many detents; positive is clockwise.
+    Rotate(i32),
+    Down,
+    Up,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DialEvent {
+    pub dial: i32,
+    pub kind: DialEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchEventKind {
+    Tap,
+    LongPress,
+    /// A drag that ended at the given point.
+    Swipe {
+        to_x: u16,
+        to_y: u16,
+    },
+}
+
+/// A touch on the LCD strip, in strip pixels from its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TouchEvent {
+    pub x: u16,
+    pub y: u16,
+    pub kind: TouchEventKind,
+}
+
+/// Anything the deck can report: keys, plus the dials and touch strip on
+/// models that have them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeckEvent {
+    Key(KeyEvent),
+    Dial(DialEvent),
+    Touch(TouchEvent),
+}
+
+#[derive(Clone, Copy)]
+struct KeyHistory {
+    pressed_at: Option<Instant>,
+    last_press: Option<Instant>,
+    long_press_sent: bool,
+}
+
+// Turns successive key state snapshots into press/release/gesture events.
+pub(crate) struct KeyEventTracker {
+    keys: Vec<KeyHistory>,
+    long_press_duration: Duration,
+    double_tap_window: Duration,
+}
+
+impl KeyEventTracker {
+    const DEFAULT_LONG_PRESS_MS: u64 = 800;
+    const DEFAULT_DOUBLE_TAP_MS: u64 = 300;
+
+    pub fn new(num_keys: usize) -> KeyEventTracker {
+        KeyEventTracker {
+            keys: vec![
+                KeyHistory {
+                    pressed_at: None,
+                    last_press: None,
+                    long_press_sent: false,
+                };
+                num_keys
+            ],
+            long_press_duration: Duration::from_millis(Self::DEFAULT_LONG_PRESS_MS),
+            double_tap_window: Duration::from_millis(Self::DEFAULT_DOUBLE_TAP_MS),
+        }
+    }
+
+    pub fn set_timing(&mut self, long_press_duration: Duration, double_tap_window: Duration) {
+        self.long_press_duration = long_press_duration;
+        self.double_tap_window = double_tap_window;
+    }
+
+    /// The pressed state of every key as of the last update.
+    pub fn key_states(&self) -> Vec<bool> {
+        self.keys
+            .iter()
+            .map(|history| history.pressed_at.is_some())
+            .collect()
+    }
+
+    /// `key_states` holds the pressed state of every key, by logical key index.
+    pub fn update(&mut self, key_states: &[bool], now: Instant) -> Vec<KeyEvent> {
+        let mut events: Vec<KeyEvent> = vec![];
+
+        for (idx, (history, is_pressed)) in self.keys.iter_mut().zip(key_states).enumerate() {
+            let key = i32::try_from(idx).unwrap();
+
+            match (history.pressed_at, *is_pressed) {
+                (None, true) => {
+                    events.push(KeyEvent {
+                        key,
+                        kind: KeyEventKind::Down,
+                    });
+                    let is_double_tap = history.last_press.is_some_and(|last_press| {
+                        now.duration_since(last_press) <= self.double_tap_window
+                    });
+                    if is_double_tap {
+                        events.push(KeyEvent {
+                            key,
+                            kind: KeyEventKind::DoubleTap,
+                        });
+                    }
+                    history.pressed_at = Some(now);
+                    // a third tap starts a new gesture rather than another double tap
+                    history.last_press = if is_double_tap { None } else { Some(now) };
+                    history.long_press_sent = false;
+                }
+                (Some(pressed_at), true) => {
+                    let held_for = now.duration_since(pressed_at);
+                    if !history.long_press_sent && held_for >= self.long_press_duration {
+                        events.push(KeyEvent {
+                            key,
+                            kind: KeyEventKind::LongPress(held_for),
+                        });
+                        history.long_press_sent = true;
+                    }
+                }
+                (Some(_), false) => {
+                    if !history.long_press_sent {
+                        events.push(KeyEvent {
+                            key,
+                            kind: KeyEventKind::Up,
+                        });
+                    }
+                    history.pressed_at = None;
+                }
+                (None, false) => {}
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use KeyEventKind::{DoubleTap, Down, LongPress, Up};
+
+    // Feeds key 0's pressed state at each time (in ms) and checks the events.
+    fn check(steps: &[(u64, bool, &[KeyEventKind])]) {
+        let start = Instant::now();
+        let mut tracker = KeyEventTracker::new(2);
+        for (ms, is_pressed, expected) in steps {
+            let events = tracker.update(&[*is_pressed, false], start + Duration::from_millis(*ms));
+            let expected: Vec<KeyEvent> = expected
+                .iter()
+                .map(|kind| KeyEvent {
+                    key: 0,
+                    kind: *kind,
+                })
+                .collect();
+            assert_eq!(events, expected, "at {}ms", ms);
+        }
+    }
+
+    #[test]
+    fn press_and_release() {
+        check(&[
+            (0, false, &[]),
+            (10, true, &[Down]),
+            (20, true, &[]),
+            (100, false, &[Up]),
+            (200, false, &[]),
+        ]);
+    }
+
+    #[test]
+    fn long_press_replaces_the_release() {
+        check(&[
+            (0, true, &[Down]),
+            (799, true, &[]),
+            (850, true, &[LongPress(Duration::from_millis(850))]),
+            // only once
+            (2000, true, &[]),
+            (2100, false, &[]),
+            // the next press is a fresh one
+            (3000, true, &[Down]),
+            (3100, false, &[Up]),
+        ]);
+    }
+
+    #[test]
+    fn double_tap() {
+        check(&[
+            (0, true, &[Down]),
+            (50, false, &[Up]),
+            (300, true, &[Down, DoubleTap]),
+            (350, false, &[Up]),
+        ]);
+    }
+
+    #[test]
+    fn slow_second_tap_is_not_a_double_tap() {
+        check(&[
+            (0, true, &[Down]),
+            (50, false, &[Up]),
+            (301, true, &[Down]),
+            (350, false, &[Up]),
+        ]);
+    }
+
+    #[test]
+    fn third_tap_starts_a_new_gesture() {
+        check(&[
+            (0, true, &[Down]),
+            (50, false, &[Up]),
+            (100, true, &[Down, DoubleTap]),
+            (150, false, &[Up]),
+            (200, true, &[Down]),
+            (250, false, &[Up]),
+            (300, true, &[Down, DoubleTap]),
+            (350, false, &[Up]),
+        ]);
+    }
+
+    #[test]
+    fn keys_are_tracked_separately() {
+        let start = Instant::now();
+        let mut tracker = KeyEventTracker::new(2);
+        tracker.update(&[true, false], start);
+        let events = tracker.update(&[false, true], start + Duration::from_millis(10));
+        assert_eq!(
+            events,
+            [
+                KeyEvent { key: 0, kind: Up },
+                KeyEvent { key: 1, kind: Down },
+            ]
+        );
+        assert_eq!(tracker.key_states(), [false, true]);
+    }
+
+    #[test]
+    fn custom_timing() {
+        let start = Instant::now();
+        let mut tracker = KeyEventTracker::new(1);
+        tracker.set_timing(Duration::from_millis(100), Duration::from_millis(1000));
+        let at = |ms| start + Duration::from_millis(ms);
+
+        tracker.update(&[true], at(0));
+        assert_eq!(
+            tracker.update(&[true], at(100)),
+            [KeyEvent {
+                key: 0,
+                kind: LongPress(Duration::from_millis(100))
+            }]
+        );
+        tracker.update(&[false], at(150));
+        assert_eq!(
+            tracker.update(&[true], at(900)),
+            [
+                KeyEvent { key: 0, kind: Down },
+                KeyEvent {
+                    key: 0,
+                    kind: DoubleTap
+                }
+            ]
+        );
+    }
+}
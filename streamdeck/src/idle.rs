@@ -0,0 +1,244 @@
+use std::time::{Duration, Instant};
+
+use crate::{DeckEvent, KeyEventKind};
+
+/// What the deck does after sitting idle for the configured timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleMode {
+    /// Fade to the given brightness percentage.
+    Dim(i32),
+    /// Fade out and clear the keys.  The images come back on wake.
+    Blank,
+}
+
+/// What changed in the last `IdleTracker::update`.
+pub(crate) struct IdleUpdate {
+    /// The events the caller should see.
+    pub events: Vec<DeckEvent>,
+    pub woke_from: Option<IdleMode>,
+    pub went_idle: Option<IdleMode>,
+}
+
+// Watches deck events for activity and decides when the deck goes idle and
+// wakes back up.  The press that wakes the deck is swallowed, along with the
+// rest of that key's events until it is released.
+pub(crate) struct IdleTracker {
+    timeout: Option<Duration>,
+    mode: IdleMode,
+    last_activity: Instant,
+    active_mode: Option<IdleMode>,
+    suppressed_keys: Vec<bool>,
+}
+
+impl IdleTracker {
+    pub fn new(num_keys: usize) -> IdleTracker {
+        IdleTracker {
+            timeout: None,
+            mode: IdleMode::Blank,
+            last_activity: Instant::now(),
+            active_mode: None,
+            suppressed_keys: vec![false; num_keys],
+        }
+    }
+
+    /// Returns the idle mode the deck woke from, if turning the timeout off
+    /// woke it.
+    pub fn set_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+        mode: IdleMode,
+        now: Instant,
+    ) -> Option<IdleMode> {
+        self.timeout = timeout;
+        self.mode = mode;
+        self.last_activity = now;
+        if timeout.is_none() {
+            self.active_mode.take()
+        } else {
+            None
+        }
+    }
+
+    /// The idle mode in effect, or None while the deck is awake.
+    pub fn active_mode(&self) -> Option<IdleMode> {
+        self.active_mode
+    }
+
+    pub fn update(&mut self, events: Vec<DeckEvent>, now: Instant) -> IdleUpdate {
+        let has_activity = events.iter().any(|event| match event {
+            DeckEvent::Key(key_event) => key_event.kind == KeyEventKind::Down,
+            _ => true,
+        });
+
+        let mut woke_from = None;
+        if has_activity {
+            self.last_activity = now;
+            woke_from = self.active_mode.take();
+        }
+
+        let events = if woke_from.is_some() {
+            for event in events.iter() {
+                if let DeckEvent::Key(key_event) = event {
+                    if key_event.kind == KeyEventKind::Down {
+                        self.suppressed_keys[usize::try_from(key_event.key).unwrap()] = true;
+                    }
+                }
+            }
+            vec![]
+        } else {
+            events
+                .into_iter()
+                .filter(|event| match event {
+                    DeckEvent::Key(key_event) => {
+                        let is_suppressed =
+                            &mut self.suppressed_keys[usize::try_from(key_event.key).unwrap()];
+                        // a long press ends without an Up, so a new press
+                        // is never swallowed either
+                        if key_event.kind == KeyEventKind::Down {
+                            *is_suppressed = false;
+                        }
+                        let was_suppressed = *is_suppressed;
+                        if key_event.kind == KeyEventKind::Up {
+                            *is_suppressed = false;
+                        }
+                        !was_suppressed
+                    }
+                    _ => true,
+                })
+                .collect()
+        };
+
+        let mut went_idle = None;
+        let timed_out = self
+            .timeout
+            .is_some_and(|timeout| now.duration_since(self.last_activity) >= timeout);
+        if self.active_mode.is_none() && woke_from.is_none() && timed_out {
+            self.active_mode = Some(self.mode);
+            went_idle = Some(self.mode);
+        }
+
+        IdleUpdate {
+            events,
+            woke_from,
+            went_idle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyEvent;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn key(key: i32, kind: KeyEventKind) -> DeckEvent {
+        DeckEvent::Key(KeyEvent { key, kind })
+    }
+
+    fn idle_tracker(start: Instant) -> IdleTracker {
+        let mut tracker = IdleTracker::new(15);
+        tracker.set_timeout(Some(TIMEOUT), IdleMode::Blank, start);
+        let update = tracker.update(vec![], start + TIMEOUT);
+        assert_eq!(update.went_idle, Some(IdleMode::Blank));
+        tracker
+    }
+
+    #[test]
+    fn goes_idle_after_the_timeout() {
+        let start = Instant::now();
+        let mut tracker = IdleTracker::new(15);
+        tracker.set_timeout(Some(TIMEOUT), IdleMode::Dim(10), start);
+
+        let update = tracker.update(vec![], start + TIMEOUT / 2);
+        assert_eq!(update.went_idle, None);
+        // a press puts the timeout off
+        tracker.update(vec![key(1, KeyEventKind::Down)], start + TIMEOUT / 2);
+        let update = tracker.update(vec![], start + TIMEOUT);
+        assert_eq!(update.went_idle, None);
+
+        let update = tracker.update(vec![], start + TIMEOUT * 2);
+        assert_eq!(update.went_idle, Some(IdleMode::Dim(10)));
+        assert_eq!(tracker.active_mode(), Some(IdleMode::Dim(10)));
+        // only once
+        let update = tracker.update(vec![], start + TIMEOUT * 3);
+        assert_eq!(update.went_idle, None);
+    }
+
+    #[test]
+    fn swallows_the_waking_press_until_it_is_released() {
+        let start = Instant::now();
+        let mut tracker = idle_tracker(start);
+        let now = start + TIMEOUT * 2;
+
+        let update = tracker.update(vec![key(3, KeyEventKind::Down)], now);
+        assert_eq!(update.woke_from, Some(IdleMode::Blank));
+        assert_eq!(update.events, []);
+        assert_eq!(tracker.active_mode(), None);
+
+        let steps = [
+            (
+                vec![
+                    key(3, KeyEventKind::LongPress(Duration::from_millis(800))),
+                    key(4, KeyEventKind::Down),
+                ],
+                vec![key(4, KeyEventKind::Down)],
+            ),
+            (vec![key(3, KeyEventKind::Up)], vec![]),
+            (
+                vec![key(3, KeyEventKind::Down), key(4, KeyEventKind::Up)],
+                vec![key(3, KeyEventKind::Down), key(4, KeyEventKind::Up)],
+            ),
+            (
+                vec![key(3, KeyEventKind::Up)],
+                vec![key(3, KeyEventKind::Up)],
+            ),
+        ];
+        for (events, expected) in steps {
+            let update = tracker.update(events, now);
+            assert_eq!(update.events, expected);
+            assert_eq!(update.woke_from, None);
+        }
+    }
+
+    #[test]
+    fn long_waking_press_does_not_swallow_the_next() {
+        let start = Instant::now();
+        let mut tracker = idle_tracker(start);
+        let now = start + TIMEOUT * 2;
+
+        tracker.update(vec![key(6, KeyEventKind::Down)], now);
+        let update = tracker.update(
+            vec![key(6, KeyEventKind::LongPress(Duration::from_secs(1)))],
+            now,
+        );
+        assert_eq!(update.events, []);
+        // released without an Up, since it was a long press
+        let update = tracker.update(vec![key(6, KeyEventKind::Down)], now);
+        assert_eq!(update.events, [key(6, KeyEventKind::Down)]);
+    }
+
+    #[test]
+    fn turning_the_timeout_off_wakes_the_deck() {
+        let start = Instant::now();
+        let mut tracker = idle_tracker(start);
+        let now = start + TIMEOUT * 2;
+
+        assert_eq!(
+            tracker.set_timeout(None, IdleMode::Blank, now),
+            Some(IdleMode::Blank)
+        );
+        assert_eq!(tracker.active_mode(), None);
+        let update = tracker.update(vec![key(0, KeyEventKind::Down)], now + TIMEOUT * 10);
+        assert_eq!(update.events, [key(0, KeyEventKind::Down)]);
+        assert_eq!(update.went_idle, None);
+
+        // changing the timeout leaves an idle deck idle
+        let mut tracker = idle_tracker(start);
+        assert_eq!(
+            tracker.set_timeout(Some(TIMEOUT), IdleMode::Blank, now),
+            None
+        );
+        assert_eq!(tracker.active_mode(), Some(IdleMode::Blank));
+    }
+}
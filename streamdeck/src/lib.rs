@@ -1,160 +1,984 @@
 extern crate image;
 
-use log::info;
+use log::{info, warn};
 
-use hidapi::*;
-use image::imageops::FilterType;
+use image::codecs::jpeg::JpegEncoder;
 use image::DynamicImage;
 use image::GenericImageView;
+use image::{ImageBuffer, Rgb};
 use std::cmp;
-use std::ffi::CStr;
-use std::ffi::CString;
+use std::sync::Mutex;
+use std::{thread, time};
+
+use self::cache::{hash_image, ImageCache, ResizeMode};
+use self::events::KeyEventTracker;
+use self::idle::IdleTracker;
+use self::orientation::{orient_for_device, unorient_from_device};
+use self::text::render_text;
+
+mod animation;
+#[cfg(feature = "tokio")]
+mod async_deck;
+mod cache;
+mod error;
+mod events;
+mod idle;
+mod model;
+mod orientation;
+mod text;
+mod transport;
+mod virtual_deck;
+
+pub use self::animation::{Animation, AnimationFrame, AnimationScheduler};
+#[cfg(feature = "tokio")]
+pub use self::async_deck::{AsyncStreamDeck, DeckEventStream};
+pub use self::error::StreamDeckError;
+pub use self::events::{
+    DeckEvent, DialEvent, DialEventKind, KeyEvent, KeyEventKind, TouchEvent, TouchEventKind,
+};
+pub use self::idle::IdleMode;
+pub use self::model::{
+    ImageFormat, ModelDescriptor, Protocol, ReportLayout, Rotation, StreamDeckModel,
+};
+pub use self::orientation::Orientation;
+pub use self::text::{HorizontalAlignment, TextStyle, VerticalAlignment};
+pub use self::transport::{HidTransport, MockTransport, StreamDeckInfo, Transport};
+pub use self::virtual_deck::VirtualTransport;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TouchStripArea {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+// What was last sent to the device, so it can be replayed after a reconnect.
+struct DisplayState {
+    brightness: Option<i32>,
+    brightness_report: Option<Vec<u8>>,
+    key_images: Vec<Option<Vec<u8>>>,
+    // Hash of the image each key shows, so unchanged keys aren't resent.
+    key_image_hashes: Vec<Option<u64>>,
+    touch_strip_images: Vec<(TouchStripArea, Vec<u8>)>,
+}
 
 pub struct StreamDeckController {
-    hid_device: HidDevice,
+    transport: Mutex<Box<dyn Transport>>,
+    descriptor: &'static ModelDescriptor,
+    orientation: Mutex<Orientation>,
+    display_state: Mutex<DisplayState>,
+    image_cache: Mutex<ImageCache>,
+    key_event_tracker: Mutex<KeyEventTracker>,
+    idle_tracker: Mutex<IdleTracker>,
+    dial_states: Mutex<Vec<bool>>,
 }
 
 impl StreamDeckController {
-    const VENDOR_ID: u16 = 0x0fd9;
-    const PRODUCT_ID: u16 = 0x0060;
-    const ICON_SIZE: u32 = 72;
+    const BMP_HEADER_SIZE: u32 = 54;
+    const JPEG_QUALITY: u8 = 95;
+    const READ_TIMEOUT_MS: i32 = 500;
+    // Dial and touch strip reports are longer than a key report on the Plus.
+    const CONTROL_REPORT_LENGTH: usize = 14;
+    const TOUCH_STRIP_HEADER_LENGTH: usize = 16;
+    const KEY_REPORT_TYPE: u8 = 0x00;
+    const TOUCH_REPORT_TYPE: u8 = 0x02;
+    const DIAL_REPORT_TYPE: u8 = 0x03;
+    const FADE_STEP_MS: u64 = 20;
+    const IDLE_FADE_MS: u64 = 1000;
+    const WAKE_FADE_MS: u64 = 250;
+    // Assumed when nothing has set the brightness yet.
+    const DEFAULT_BRIGHTNESS: i32 = 100;
 
-    const NUM_KEYS: i32 = 15;
-    const NUM_COLUMNS: i32 = 5;
-    // const NUM_ROWS: i32 = 3;
+    pub fn new() -> Result<StreamDeckController, StreamDeckError> {
+        let transport = HidTransport::open_first()?;
+        Self::from_hid_transport(transport)
+    }
 
-    const PAGE_PACKET_SIZE: usize = 8191;
-    const NUM_FIRST_PAGE_PIXELS: u32 = 2583;
-    const NUM_SECOND_PAGE_PIXELS: u32 = 2601;
+    /// Lists every attached Stream Deck, so each can be opened by serial number.
+    pub fn list_devices() -> Result<Vec<StreamDeckInfo>, StreamDeckError> {
+        HidTransport::enumerate()
+    }
 
-    pub fn new() -> Result<StreamDeckController, HidError> {
-        let mut hid_api = HidApi::new()?;
+    pub fn from_serial(serial_number: &str) -> Result<StreamDeckController, StreamDeckError> {
+        let transport = HidTransport::open_serial(serial_number)?;
+        Self::from_hid_transport(transport)
+    }
 
-        hid_api.refresh_devices().unwrap();
-        for dev in hid_api.device_list() {
-            info!("Device:");
-            info!("path: {:?}", dev.path());
-            info!("manufacturer: {:?}", Some(dev.manufacturer_string()));
-            info!("vendorid: {}", dev.vendor_id());
-            info!("productid: {}", dev.product_id());
-            info!("---");
-        }
+    pub fn from_device_path(device_path: &str) -> Result<StreamDeckController, StreamDeckError> {
+        let transport = HidTransport::open_path(device_path)?;
+        Self::from_hid_transport(transport)
+    }
 
-        let hid_device = hid_api.open(Self::VENDOR_ID, Self::PRODUCT_ID)?;
-        let stream_deck = StreamDeckController { hid_device };
+    fn from_hid_transport(
+        transport: HidTransport,
+    ) -> Result<StreamDeckController, StreamDeckError> {
+        let product_id = transport.product_id();
+        let model = StreamDeckModel::from_product_id(product_id).ok_or_else(|| {
+            StreamDeckError::UnsupportedDevice(format!("product id {:#06x}", product_id))
+        })?;
+        let stream_deck = Self::from_transport(Box::new(transport), model);
+
+        match (
+            stream_deck.get_serial_number(),
+            stream_deck.get_firmware_version(),
+        ) {
+            (Ok(serial_number), Ok(firmware_version)) => info!(
+                "Opened {} (serial number: {}, firmware: {})",
+                stream_deck.get_model_name(),
+                serial_number,
+                firmware_version
+            ),
+            (Err(e), _) | (_, Err(e)) => warn!(
+                "Opened {} but could not read its device info: {}",
+                stream_deck.get_model_name(),
+                e
+            ),
+        }
 
         Ok(stream_deck)
     }
 
-    pub fn from_device_path(device_path: &str) -> Result<StreamDeckController, HidError> {
-        let hid_api = HidApi::new().unwrap();
-
-        let path_cstring: CString = CString::new(device_path).unwrap();
-        let path_cstr: &CStr = path_cstring.as_c_str();
-        let hid_device = hid_api.open_path(path_cstr)?;
+    /// Drives a deck of the given model over any transport, e.g. a `MockTransport`.
+    pub fn from_transport(
+        transport: Box<dyn Transport>,
+        model: StreamDeckModel,
+    ) -> StreamDeckController {
+        let descriptor = model.descriptor();
+        let num_keys = usize::try_from(descriptor.key_count).unwrap();
+        StreamDeckController {
+            transport: Mutex::new(transport),
+            descriptor,
+            orientation: Mutex::new(Orientation::default()),
+            display_state: Mutex::new(DisplayState {
+                brightness: None,
+                brightness_report: None,
+                key_images: vec![None; num_keys],
+                key_image_hashes: vec![None; num_keys],
+                touch_strip_images: vec![],
+            }),
+            image_cache: Mutex::new(ImageCache::new()),
+            key_event_tracker: Mutex::new(KeyEventTracker::new(num_keys)),
+            idle_tracker: Mutex::new(IdleTracker::new(num_keys)),
+            dial_states: Mutex::new(vec![false; usize::try_from(descriptor.dial_count).unwrap()]),
+        }
+    }
 
-        let stream_deck = StreamDeckController { hid_device };
-        Ok(stream_deck)
+    pub fn get_descriptor(&self) -> &'static ModelDescriptor {
+        self.descriptor
     }
 
     pub fn get_num_keys(&self) -> i32 {
-        Self::NUM_KEYS
+        self.descriptor.key_count
+    }
+
+    pub fn get_num_dials(&self) -> i32 {
+        self.descriptor.dial_count
     }
 
-    pub fn create_keystates_buf(&self) -> Vec<u8> {
-        vec![0; usize::try_from(Self::NUM_KEYS + 1).unwrap()]
+    fn has_controls(&self) -> bool {
+        self.descriptor.dial_count > 0 || self.descriptor.touch_strip_size.is_some()
     }
 
-    pub fn get_keystates(&self) -> Vec<u8> // todo: needs return type
-    {
-        let timeout_ms = 500;
+    fn create_keystates_buf(&self) -> Vec<u8> {
+        let key_report_length = self.descriptor.reports.key_state_offset
+            + usize::try_from(self.get_num_keys()).unwrap();
+        if self.has_controls() {
+            vec![0; cmp::max(key_report_length, Self::CONTROL_REPORT_LENGTH)]
+        } else {
+            vec![0; key_report_length]
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.transport.lock().unwrap().is_connected()
+    }
+
+    // Reads the key state report, waiting up to `timeout_ms` for a change.
+    // Returns None if nothing changed.
+    //
+    // While the deck is unplugged this keeps trying to reopen it and reports
+    // every key as released, so callers can keep polling as normal.
+    fn read_keystates(&self, timeout_ms: i32) -> Result<Option<Vec<u8>>, StreamDeckError> {
         let mut keystates_buf = self.create_keystates_buf();
-        self.hid_device
-            .read_timeout(keystates_buf.as_mut(), timeout_ms)
-            .unwrap();
-        keystates_buf
+
+        if !self.is_connected() {
+            let reconnected = self.reconnect().unwrap_or_else(|e| {
+                warn!("failed to reopen {}: {}", self.descriptor.name, e);
+                false
+            });
+            if !reconnected {
+                thread::sleep(time::Duration::from_millis(timeout_ms as u64));
+                return Ok(Some(keystates_buf));
+            }
+        }
+
+        match self
+            .with_transport(|transport| transport.read_timeout(keystates_buf.as_mut(), timeout_ms))
+        {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(keystates_buf)),
+            Err(StreamDeckError::Disconnected) => Ok(Some(self.create_keystates_buf())),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Polls the deck and returns any key events since the last call, using
+    /// logical key indexes.  Blocks for up to half a second.
+    ///
+    /// Dial and touch strip events are dropped; use `read_events` for those.
+    pub fn read_key_events(&self) -> Result<Vec<KeyEvent>, StreamDeckError> {
+        Ok(self
+            .read_events()?
+            .into_iter()
+            .filter_map(|event| match event {
+                DeckEvent::Key(key_event) => Some(key_event),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Like `read_key_events`, but also returns dial and touch strip events on
+    /// models that have them.
+    pub fn read_events(&self) -> Result<Vec<DeckEvent>, StreamDeckError> {
+        self.read_events_timeout(time::Duration::from_millis(Self::READ_TIMEOUT_MS as u64))
+    }
+
+    /// Like `read_events`, but blocks for at most `timeout`.  The deck is
+    /// locked while waiting, so a short timeout lets writes from other
+    /// threads through sooner.
+    pub fn read_events_timeout(
+        &self,
+        timeout: time::Duration,
+    ) -> Result<Vec<DeckEvent>, StreamDeckError> {
+        let timeout_ms = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        let keystates = self.read_keystates(timeout_ms)?;
+        let mut events: Vec<DeckEvent> = vec![];
+
+        let key_states = match keystates {
+            Some(report) if self.has_controls() && report[1] != Self::KEY_REPORT_TYPE => {
+                events.extend(self.parse_control_report(&report));
+                None
+            }
+            Some(keystates) => Some(self.parse_key_states(&keystates)),
+            None => None,
+        };
+
+        // keep updating the tracker on other reports so long presses still fire
+        let now = time::Instant::now();
+        let key_events = {
+            let mut key_event_tracker = self.key_event_tracker.lock().unwrap();
+            let key_states = key_states.unwrap_or_else(|| key_event_tracker.key_states());
+            key_event_tracker.update(&key_states, now)
+        };
+        let events = key_events
+            .into_iter()
+            .map(DeckEvent::Key)
+            .chain(events)
+            .collect();
+
+        let idle_update = self.idle_tracker.lock().unwrap().update(events, now);
+        let idle_result = match (idle_update.woke_from, idle_update.went_idle) {
+            (Some(idle_mode), _) => self.wake_from_idle(idle_mode),
+            (None, Some(idle_mode)) => self.enter_idle(idle_mode),
+            (None, None) => Ok(()),
+        };
+        match idle_result {
+            Ok(()) | Err(StreamDeckError::Disconnected) => Ok(idle_update.events),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Dims or blanks the deck once no key, dial or touch strip has been used
+    /// for `timeout`.  The next press wakes it back up, restoring the images
+    /// and brightness, and is not reported as a key event.  `None` turns the
+    /// idle timeout off, waking the deck if it is idle.  Models without a
+    /// display never go idle, so every press is reported.
+    pub fn set_idle_timeout(
+        &self,
+        timeout: Option<time::Duration>,
+        idle_mode: IdleMode,
+    ) -> Result<(), StreamDeckError> {
+        if !self.descriptor.has_display {
+            return Ok(());
+        }
+        let woke_from =
+            self.idle_tracker
+                .lock()
+                .unwrap()
+                .set_timeout(timeout, idle_mode, time::Instant::now());
+        match woke_from.map(|idle_mode| self.wake_from_idle(idle_mode)) {
+            None | Some(Ok(())) | Some(Err(StreamDeckError::Disconnected)) => Ok(()),
+            Some(Err(e)) => Err(e),
+        }
+    }
+
+    /// True while the deck is dimmed or blanked by the idle timeout.
+    pub fn is_idle(&self) -> bool {
+        self.get_idle_mode().is_some()
+    }
+
+    fn get_idle_mode(&self) -> Option<IdleMode> {
+        self.idle_tracker.lock().unwrap().active_mode()
+    }
+
+    fn is_blanked(&self) -> bool {
+        self.get_idle_mode() == Some(IdleMode::Blank)
+    }
+
+    fn get_brightness(&self) -> i32 {
+        self.display_state
+            .lock()
+            .unwrap()
+            .brightness
+            .unwrap_or(Self::DEFAULT_BRIGHTNESS)
     }
 
-    pub fn convert_key_index(&self, key_index: i32) -> i32 {
-        let key_col = key_index % Self::NUM_COLUMNS;
-        (key_index - key_col) + ((Self::NUM_COLUMNS - 1) - key_col)
+    // Dimming never brightens a deck that is already darker.
+    fn get_dimmed_brightness(&self, percentage: i32) -> i32 {
+        cmp::min(self.get_brightness(), percentage)
     }
 
-    pub fn fill_color(&self, key_index: i32, r: u8, g: u8, b: u8) {
-        let device_key_index = self.convert_key_index(key_index);
+    fn enter_idle(&self, idle_mode: IdleMode) -> Result<(), StreamDeckError> {
+        let fade_duration = time::Duration::from_millis(Self::IDLE_FADE_MS);
 
-        let pixel: Vec<u8> = vec![b, g, r];
-        self.write_page_1(
-            device_key_index,
-            &self.create_buffer(
-                usize::try_from(Self::NUM_FIRST_PAGE_PIXELS * 3).unwrap(),
-                &pixel,
+        match idle_mode {
+            IdleMode::Dim(percentage) => self.fade_brightness_steps(
+                self.get_brightness(),
+                self.get_dimmed_brightness(percentage),
+                fade_duration,
             ),
-        );
-        self.write_page_2(
-            device_key_index,
-            &self.create_buffer(
-                usize::try_from(Self::NUM_SECOND_PAGE_PIXELS * 3).unwrap(),
-                &pixel,
+            IdleMode::Blank => {
+                self.fade_brightness_steps(self.get_brightness(), 0, fade_duration)?;
+                self.blank_display()
+            }
+        }
+    }
+
+    fn wake_from_idle(&self, idle_mode: IdleMode) -> Result<(), StreamDeckError> {
+        let fade_duration = time::Duration::from_millis(Self::WAKE_FADE_MS);
+
+        match idle_mode {
+            IdleMode::Dim(percentage) => self.fade_brightness_steps(
+                self.get_dimmed_brightness(percentage),
+                self.get_brightness(),
+                fade_duration,
             ),
-        );
+            IdleMode::Blank => {
+                self.replay_images()?;
+                self.fade_brightness_steps(0, self.get_brightness(), fade_duration)
+            }
+        }
     }
 
-    fn create_buffer(&self, buffer_length: usize, repeated_fill_data: &Vec<u8>) -> Vec<u8> {
-        let mut output_buffer = vec![0; buffer_length];
-        let copy_len = repeated_fill_data.len();
-        let max_copies = buffer_length / copy_len;
-        for idx in 0..max_copies {
-            let start_offset = idx * copy_len;
-            let end_offset = start_offset + copy_len;
-            output_buffer[start_offset..end_offset]
-                .copy_from_slice(&repeated_fill_data[0..copy_len]);
+    // Draws black over every key and the touch strip without touching the
+    // display state, so the real images can be replayed on wake.
+    fn blank_display(&self) -> Result<(), StreamDeckError> {
+        let icon_size = self.descriptor.icon_size;
+        let black_key = DynamicImage::ImageRgb8(ImageBuffer::new(icon_size, icon_size));
+        let black_key_buf = match self.descriptor.image_format {
+            ImageFormat::Bmp => self.encode_bmp(&black_key),
+            ImageFormat::Jpeg => self.encode_jpeg(&black_key)?,
+        };
+        for device_key_index in 0..self.get_num_keys() {
+            self.write_image_pages(u8::try_from(device_key_index).unwrap(), &black_key_buf)?;
         }
 
-        output_buffer
+        if let Some((width, height)) = self.descriptor.touch_strip_size {
+            let black_strip = DynamicImage::ImageRgb8(ImageBuffer::new(width, height));
+            let area = TouchStripArea {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            };
+            self.write_touch_strip_pages(area, &self.encode_jpeg(&black_strip)?)?;
+        }
+        Ok(())
     }
 
-    pub fn fill_all_color(&self, r: u8, g: u8, b: u8) {
-        for key_index in 0..Self::NUM_KEYS - 1 {
-            self.fill_color(key_index, r, g, b);
+    // Input report layout for the Plus, after the report id and type bytes:
+    //   touch: [_, _, kind, _, x lo, x hi, y lo, y hi, to_x lo, to_x hi, to_y lo, to_y hi]
+    //   dial:  [_, _, 0x00 (press) | 0x01 (rotate), one signed byte per dial...]
+    fn parse_control_report(&self, report: &[u8]) -> Vec<DeckEvent> {
+        let read_u16 = |offset: usize| u16::from_le_bytes([report[offset], report[offset + 1]]);
+
+        match report[1] {
+            Self::TOUCH_REPORT_TYPE => {
+                let kind = match report[4] {
+                    0x01 => TouchEventKind::Tap,
+                    0x02 => TouchEventKind::LongPress,
+                    0x03 => TouchEventKind::Swipe {
+                        to_x: read_u16(10),
+                        to_y: read_u16(12),
+                    },
+                    _ => return vec![],
+                };
+                vec![DeckEvent::Touch(TouchEvent {
+                    x: read_u16(6),
+                    y: read_u16(8),
+                    kind,
+                })]
+            }
+            Self::DIAL_REPORT_TYPE => {
+                let num_dials = usize::try_from(self.get_num_dials()).unwrap();
+                let dial_values = &report[5..5 + num_dials];
+                let mut events: Vec<DeckEvent> = vec![];
+
+                if report[4] == 0x01 {
+                    for (dial_index, value) in dial_values.iter().enumerate() {
+                        let ticks = i32::from(*value as i8);
+                        if ticks != 0 {
+                            events.push(DeckEvent::Dial(DialEvent {
+                                dial: i32::try_from(dial_index).unwrap(),
+                                kind: DialEventKind::Rotate(ticks),
+                            }));
+                        }
+                    }
+                } else {
+                    let mut dial_states = self.dial_states.lock().unwrap();
+                    for (dial_index, (was_pressed, value)) in
+                        dial_states.iter_mut().zip(dial_values).enumerate()
+                    {
+                        let is_pressed = *value > 0;
+                        if is_pressed != *was_pressed {
+                            events.push(DeckEvent::Dial(DialEvent {
+                                dial: i32::try_from(dial_index).unwrap(),
+                                kind: if is_pressed {
+                                    DialEventKind::Down
+                                } else {
+                                    DialEventKind::Up
+                                },
+                            }));
+                            *was_pressed = is_pressed;
+                        }
+                    }
+                }
+                events
+            }
+            _ => vec![],
         }
     }
 
-    pub fn set_image(&self, key_index: i32, image_file_path: &str) {
-        if key_index < 0 || key_index >= Self::NUM_KEYS {
-            // TODO: how to do error here?! ImageError::Parameter()
+    fn parse_key_states(&self, keystates: &[u8]) -> Vec<bool> {
+        let key_state_offset = self.descriptor.reports.key_state_offset;
+
+        let mut key_states = vec![false; usize::try_from(self.get_num_keys()).unwrap()];
+        for device_key_index in 0..self.get_num_keys() {
+            let is_pressed =
+                keystates[key_state_offset + usize::try_from(device_key_index).unwrap()] > 0;
+            let key_index = self.to_logical_key_index(device_key_index);
+            key_states[usize::try_from(key_index).unwrap()] = is_pressed;
         }
-        let img = image::open(image_file_path).unwrap();
-        let resized_image = img.resize(Self::ICON_SIZE, Self::ICON_SIZE, FilterType::Triangle);
-        self.set_image_exact(key_index, resized_image);
+        key_states
     }
 
-    pub fn clear_key(&self, key_index: i32) {
-        self.fill_color(key_index, 0, 0, 0);
+    /// Sets how long a key must be held to send `LongPress`, and how soon a
+    /// second press must follow the first to send `DoubleTap`.
+    pub fn set_key_event_timing(
+        &self,
+        long_press_duration: time::Duration,
+        double_tap_window: time::Duration,
+    ) {
+        self.key_event_tracker
+            .lock()
+            .unwrap()
+            .set_timing(long_press_duration, double_tap_window);
     }
 
-    pub fn clear_all_keys(&self) {
-        for key_index in 0..Self::NUM_KEYS - 1 {
-            self.clear_key(key_index);
+    /// Looks for the deck on the bus again and reopens it, restoring the last
+    /// brightness and key images.  Returns false if it is still missing.
+    pub fn reconnect(&self) -> Result<bool, StreamDeckError> {
+        if !self.transport.lock().unwrap().reconnect()? {
+            return Ok(false);
         }
+
+        info!("{} reconnected", self.descriptor.name);
+        self.replay_display_state()?;
+        Ok(true)
     }
 
-    pub fn reset(&self) {}
+    fn replay_display_state(&self) -> Result<(), StreamDeckError> {
+        let brightness_report = self.display_state.lock().unwrap().brightness_report.clone();
 
-    pub fn set_brightness(&self, percentage: i32) {
-        let clamped_percentage = num::clamp(percentage, 0, 100);
-        let command_buffer = vec![
-            0x05,
-            0x55,
-            0xaa,
-            0xd1,
-            0x01,
-            u8::try_from(clamped_percentage).unwrap(),
-        ];
-        self.send_feature_report(&self.pad_buffer_to_length(&command_buffer, 17));
+        match self.get_idle_mode() {
+            Some(IdleMode::Dim(percentage)) => {
+                let brightness = self.get_dimmed_brightness(percentage);
+                self.send_feature_report(&self.get_brightness_report(brightness))?;
+                self.replay_images()
+            }
+            Some(IdleMode::Blank) => {
+                self.send_feature_report(&self.get_brightness_report(0))?;
+                self.blank_display()
+            }
+            None => {
+                if let Some(brightness_report) = brightness_report {
+                    self.send_feature_report(&brightness_report)?;
+                }
+                self.replay_images()
+            }
+        }
+    }
+
+    fn replay_images(&self) -> Result<(), StreamDeckError> {
+        let (key_images, touch_strip_images) = {
+            let display_state = self.display_state.lock().unwrap();
+            (
+                display_state.key_images.clone(),
+                display_state.touch_strip_images.clone(),
+            )
+        };
+
+        for (device_key_index, image_buf) in key_images.iter().enumerate() {
+            if let Some(image_buf) = image_buf {
+                self.write_image_pages(u8::try_from(device_key_index).unwrap(), image_buf)?;
+            }
+        }
+        for (area, image_buf) in touch_strip_images.iter() {
+            self.write_touch_strip_pages(*area, image_buf)?;
+        }
+        Ok(())
+    }
+
+    /// Sets how the deck is mounted.  Keys are renumbered to match, and images
+    /// drawn from now on are turned to look upright; redraw any keys already
+    /// showing an image.  The touch strip and dials are not affected.
+    pub fn set_orientation(&self, orientation: Orientation) {
+        *self.orientation.lock().unwrap() = orientation;
+        self.display_state
+            .lock()
+            .unwrap()
+            .key_image_hashes
+            .fill(None);
+    }
+
+    pub fn get_orientation(&self) -> Orientation {
+        *self.orientation.lock().unwrap()
+    }
+
+    /// Number of key columns as seen by the user, after the orientation.
+    pub fn get_num_columns(&self) -> i32 {
+        self.get_logical_size().0
+    }
+
+    /// Number of key rows as seen by the user, after the orientation.
+    pub fn get_num_rows(&self) -> i32 {
+        self.get_logical_size().1
+    }
+
+    fn get_logical_size(&self) -> (i32, i32) {
+        self.get_orientation()
+            .logical_size(self.descriptor.columns, self.descriptor.rows)
+    }
+
+    fn to_logical_key_index(&self, device_key_index: i32) -> i32 {
+        self.get_orientation().to_logical_key_index(
+            self.convert_key_index(device_key_index),
+            self.descriptor.columns,
+            self.descriptor.rows,
+        )
+    }
+
+    fn to_device_key_index(&self, key_index: i32) -> i32 {
+        self.convert_key_index(self.get_orientation().to_upright_key_index(
+            key_index,
+            self.descriptor.columns,
+            self.descriptor.rows,
+        ))
+    }
+
+    // Maps between upright and device key indexes (the mapping is its own inverse).
+    fn convert_key_index(&self, key_index: i32) -> i32 {
+        if !self.descriptor.reversed_key_columns {
+            return key_index;
+        }
+        let num_columns = self.descriptor.columns;
+        let key_col = key_index % num_columns;
+        (key_index - key_col) + ((num_columns - 1) - key_col)
+    }
+
+    pub fn fill_color(&self, key_index: i32, r: u8, g: u8, b: u8) -> Result<(), StreamDeckError> {
+        let icon_size = self.descriptor.icon_size;
+        let image = ImageBuffer::from_pixel(icon_size, icon_size, Rgb([r, g, b]));
+        self.set_image_exact(key_index, DynamicImage::ImageRgb8(image))
+    }
+
+    pub fn fill_all_color(&self, r: u8, g: u8, b: u8) -> Result<(), StreamDeckError> {
+        for key_index in 0..self.get_num_keys() {
+            self.fill_color(key_index, r, g, b)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_image(&self, key_index: i32, image_file_path: &str) -> Result<(), StreamDeckError> {
+        self.check_key_index(key_index)?;
+        if !self.descriptor.has_display {
+            return Ok(());
+        }
+        let icon_size = self.descriptor.icon_size;
+        let resized_image =
+            self.load_image(image_file_path, icon_size, icon_size, ResizeMode::Fit)?;
+        self.set_image_exact(key_index, resized_image)
+    }
+
+    fn load_image(
+        &self,
+        image_file_path: &str,
+        width: u32,
+        height: u32,
+        resize_mode: ResizeMode,
+    ) -> Result<DynamicImage, StreamDeckError> {
+        self.image_cache
+            .lock()
+            .unwrap()
+            .load(image_file_path, width, height, resize_mode)
+    }
+
+    /// Forgets every image loaded from disk, e.g. after the files have changed.
+    pub fn clear_image_cache(&self) {
+        self.image_cache.lock().unwrap().clear();
+    }
+
+    /// Stretches one image across the whole deck, one tile per key.
+    ///
+    /// `key_gap` is the spacing between keys in image pixels; the parts of the
+    /// image that fall in the gaps are not shown, so lines stay continuous
+    /// from key to key.
+    pub fn set_panel_image(
+        &self,
+        image_file_path: &str,
+        key_gap: u32,
+    ) -> Result<(), StreamDeckError> {
+        if !self.descriptor.has_display {
+            return Ok(());
+        }
+        let icon_size = self.descriptor.icon_size;
+        let (panel_width, panel_height) = self.get_panel_size(key_gap)?;
+        let panel_image =
+            self.load_image(image_file_path, panel_width, panel_height, ResizeMode::Fill)?;
+
+        for key_index in 0..self.get_num_keys() {
+            let (x, y) = self.get_key_position(key_index, key_gap);
+            let tile = panel_image.crop_imm(x, y, icon_size, icon_size);
+            self.set_image_exact(key_index, tile)?;
+        }
+        Ok(())
+    }
+
+    // Size of the keys laid out as the user sees them, `key_gap` pixels apart.
+    // Every key position within it then fits a u32 too.
+    fn get_panel_size(&self, key_gap: u32) -> Result<(u32, u32), StreamDeckError> {
+        let icon_size = self.descriptor.icon_size;
+        let length = |keys: i32| {
+            let keys = u32::try_from(keys).unwrap();
+            keys.checked_mul(icon_size)?
+                .checked_add(keys.saturating_sub(1).checked_mul(key_gap)?)
+        };
+        match (length(self.get_num_columns()), length(self.get_num_rows())) {
+            (Some(width), Some(height)) => Ok((width, height)),
+            _ => Err(StreamDeckError::InvalidKeyGap(key_gap)),
+        }
+    }
+
+    // Top left corner of a key within the panel from `get_panel_size`.
+    fn get_key_position(&self, key_index: i32, key_gap: u32) -> (u32, u32) {
+        let columns = self.get_num_columns();
+        let column = u32::try_from(key_index % columns).unwrap();
+        let row = u32::try_from(key_index / columns).unwrap();
+        let icon_size = self.descriptor.icon_size;
+        (column * (icon_size + key_gap), row * (icon_size + key_gap))
+    }
+
+    /// Composes the images last sent to the keys into one picture, laid out
+    /// as the user sees the deck with `key_gap` pixels between keys.  Keys
+    /// that were never drawn are black.  The touch strip, if any, goes below.
+    pub fn render_snapshot(&self, key_gap: u32) -> Result<DynamicImage, StreamDeckError> {
+        if !self.descriptor.has_display {
+            return Err(StreamDeckError::NoDisplay(self.descriptor.name));
+        }
+
+        let (key_images, touch_strip_images) = {
+            let display_state = self.display_state.lock().unwrap();
+            (
+                display_state.key_images.clone(),
+                display_state.touch_strip_images.clone(),
+            )
+        };
+
+        let (panel_width, panel_height) = self.get_panel_size(key_gap)?;
+        let (snapshot_width, snapshot_height) = match self.descriptor.touch_strip_size {
+            Some((width, height)) => (
+                cmp::max(panel_width, width),
+                panel_height
+                    .checked_add(key_gap)
+                    .and_then(|strip_y| strip_y.checked_add(height))
+                    .ok_or(StreamDeckError::InvalidKeyGap(key_gap))?,
+            ),
+            None => (panel_width, panel_height),
+        };
+        let mut snapshot = DynamicImage::new_rgb8(snapshot_width, snapshot_height);
+
+        let orientation = self.get_orientation();
+        for key_index in 0..self.get_num_keys() {
+            let device_key_index = usize::try_from(self.to_device_key_index(key_index)).unwrap();
+            let Some(image_buf) = &key_images[device_key_index] else {
+                continue;
+            };
+            let image = orientation.to_logical_image(unorient_from_device(
+                self.descriptor,
+                self.decode_key_image(image_buf)?,
+            ));
+            let (x, y) = self.get_key_position(key_index, key_gap);
+            image::imageops::overlay(&mut snapshot, &image, i64::from(x), i64::from(y));
+        }
+
+        for (area, image_buf) in touch_strip_images.iter() {
+            let image = image::load_from_memory_with_format(image_buf, image::ImageFormat::Jpeg)?;
+            let y = panel_height + key_gap + area.y;
+            image::imageops::overlay(&mut snapshot, &image, i64::from(area.x), i64::from(y));
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Saves `render_snapshot` to a file; the format follows the extension.
+    pub fn save_snapshot(
+        &self,
+        image_file_path: &str,
+        key_gap: u32,
+    ) -> Result<(), StreamDeckError> {
+        self.render_snapshot(key_gap)?.save(image_file_path)?;
+        Ok(())
+    }
+
+    /// Renders a text label into a key sized image without sending it.
+    pub fn render_key(&self, text: &str, style: &TextStyle) -> DynamicImage {
+        DynamicImage::ImageRgb8(render_text(self.descriptor.icon_size, text, style))
+    }
+
+    pub fn set_text(
+        &self,
+        key_index: i32,
+        text: &str,
+        style: &TextStyle,
+    ) -> Result<(), StreamDeckError> {
+        self.check_key_index(key_index)?;
+        self.set_image_exact(key_index, self.render_key(text, style))
+    }
+
+    /// Stretches an image across the whole touch strip.
+    pub fn set_touch_strip_image(&self, image_file_path: &str) -> Result<(), StreamDeckError> {
+        let (width, height) = self.get_touch_strip_size()?;
+        let img = self.load_image(image_file_path, width, height, ResizeMode::Fill)?;
+        self.set_touch_strip_area(0, 0, img)
+    }
+
+    /// Draws an image onto the part of the touch strip above a dial.
+    pub fn set_dial_image(
+        &self,
+        dial_index: i32,
+        image_file_path: &str,
+    ) -> Result<(), StreamDeckError> {
+        let (width, height) = self.get_touch_strip_size()?;
+        if dial_index < 0 || dial_index >= self.get_num_dials() {
+            return Err(StreamDeckError::InvalidDial(dial_index));
+        }
+
+        let region_width = width / u32::try_from(self.get_num_dials()).unwrap();
+        let img = self.load_image(image_file_path, region_width, height, ResizeMode::Fill)?;
+        self.set_touch_strip_area(u32::try_from(dial_index).unwrap() * region_width, 0, img)
+    }
+
+    /// Draws an image onto the touch strip with its top left corner at `x`, `y`.
+    /// The image is sent as is, so it must fit within the strip.
+    pub fn set_touch_strip_area(
+        &self,
+        x: u32,
+        y: u32,
+        image: DynamicImage,
+    ) -> Result<(), StreamDeckError> {
+        let (width, height) = self.get_touch_strip_size()?;
+        let area = TouchStripArea {
+            x,
+            y,
+            width: image.width(),
+            height: image.height(),
+        };
+        // an area that would run past u32::MAX is off the strip too
+        let fits = |start: u32, length: u32, limit: u32| {
+            length > 0 && start.checked_add(length).is_some_and(|end| end <= limit)
+        };
+        if !fits(x, area.width, width) || !fits(y, area.height, height) {
+            return Err(StreamDeckError::InvalidTouchStripArea {
+                x,
+                y,
+                width: area.width,
+                height: area.height,
+            });
+        }
+
+        let image_buf = self.encode_jpeg(&image)?;
+        {
+            let mut display_state = self.display_state.lock().unwrap();
+            display_state
+                .touch_strip_images
+                .retain(|(existing_area, _)| *existing_area != area);
+            display_state
+                .touch_strip_images
+                .push((area, image_buf.clone()));
+        }
+        if self.is_blanked() {
+            return Ok(());
+        }
+        self.write_touch_strip_pages(area, &image_buf)
+    }
+
+    fn get_touch_strip_size(&self) -> Result<(u32, u32), StreamDeckError> {
+        self.descriptor
+            .touch_strip_size
+            .ok_or(StreamDeckError::NoTouchStrip(self.descriptor.name))
+    }
+
+    pub fn clear_key(&self, key_index: i32) -> Result<(), StreamDeckError> {
+        self.fill_color(key_index, 0, 0, 0)
+    }
+
+    pub fn clear_all_keys(&self) -> Result<(), StreamDeckError> {
+        for key_index in 0..self.get_num_keys() {
+            self.clear_key(key_index)?;
+        }
+        Ok(())
+    }
+
+    /// Clears every key image and shows the Elgato logo.  Brightness is kept.
+    pub fn reset(&self) -> Result<(), StreamDeckError> {
+        if !self.descriptor.has_display {
+            return Ok(());
+        }
+        let command_buffer = match self.descriptor.reports.protocol {
+            Protocol::Gen1 => vec![0x0b, 0x63],
+            Protocol::Gen2 => vec![0x03, 0x02],
+        };
+
+        {
+            let mut display_state = self.display_state.lock().unwrap();
+            display_state.key_images.fill(None);
+            display_state.key_image_hashes.fill(None);
+            display_state.touch_strip_images.clear();
+        }
+        self.send_feature_report(&self.pad_buffer_to_length(
+            &command_buffer,
+            self.descriptor.reports.feature_report_length,
+        ))
+    }
+
+    pub fn get_model_name(&self) -> &'static str {
+        self.descriptor.name
+    }
+
+    pub fn get_firmware_version(&self) -> Result<String, StreamDeckError> {
+        match self.descriptor.reports.protocol {
+            Protocol::Gen1 => self.read_feature_string(0x04, 5),
+            Protocol::Gen2 => self.read_feature_string(0x05, 6),
+        }
+    }
+
+    pub fn get_serial_number(&self) -> Result<String, StreamDeckError> {
+        match self.descriptor.reports.protocol {
+            Protocol::Gen1 => self.read_feature_string(0x03, 5),
+            Protocol::Gen2 => self.read_feature_string(0x06, 2),
+        }
+    }
+
+    // Reads a feature report holding a nul terminated ascii string at `offset`.
+    fn read_feature_string(&self, report_id: u8, offset: usize) -> Result<String, StreamDeckError> {
+        let mut report_buf = vec![0; self.descriptor.reports.feature_report_length];
+        report_buf[0] = report_id;
+        let len = self.with_transport(|transport| transport.get_feature_report(&mut report_buf))?;
+
+        let value = report_buf.get(offset..len).unwrap_or_default();
+        let value_len = value.iter().position(|b| *b == 0).unwrap_or(value.len());
+        Ok(String::from_utf8_lossy(&value[0..value_len])
+            .trim()
+            .to_string())
     }
 
-    fn pad_buffer_to_length(&self, input_buffer: &Vec<u8>, desired_length: usize) -> Vec<u8> {
+    /// Sets the brightness.  While the deck is idle the new brightness is kept
+    /// for when it wakes up.
+    pub fn set_brightness(&self, percentage: i32) -> Result<(), StreamDeckError> {
+        if !self.descriptor.has_display {
+            return Ok(());
+        }
+        let percentage = num::clamp(percentage, 0, 100);
+        let brightness_report = self.get_brightness_report(percentage);
+
+        {
+            let mut display_state = self.display_state.lock().unwrap();
+            display_state.brightness = Some(percentage);
+            display_state.brightness_report = Some(brightness_report.clone());
+        }
+        if self.is_idle() {
+            return Ok(());
+        }
+        self.send_feature_report(&brightness_report)
+    }
+
+    /// Steps the brightness from its current level to `percentage` over
+    /// `duration`.  Blocks until the fade is done.
+    pub fn fade_brightness(
+        &self,
+        percentage: i32,
+        duration: time::Duration,
+    ) -> Result<(), StreamDeckError> {
+        if self.descriptor.has_display && !self.is_idle() {
+            let percentage = num::clamp(percentage, 0, 100);
+            self.fade_brightness_steps(self.get_brightness(), percentage, duration)?;
+        }
+        self.set_brightness(percentage)
+    }
+
+    // Sends the brightness levels between `from` and `to` without storing them.
+    fn fade_brightness_steps(
+        &self,
+        from: i32,
+        to: i32,
+        duration: time::Duration,
+    ) -> Result<(), StreamDeckError> {
+        let step_duration = time::Duration::from_millis(Self::FADE_STEP_MS);
+        let num_steps = i32::try_from(duration.as_millis() / step_duration.as_millis())
+            .unwrap_or(i32::MAX)
+            .max(1);
+
+        for step in 1..=num_steps {
+            let percentage = fade_step(from, to, step, num_steps);
+            self.send_feature_report(&self.get_brightness_report(percentage))?;
+            if step < num_steps {
+                thread::sleep(step_duration);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_brightness_report(&self, percentage: i32) -> Vec<u8> {
+        let clamped_percentage = num::clamp(percentage, 0, 100) as u8;
+        let command_buffer = match self.descriptor.reports.protocol {
+            Protocol::Gen1 => vec![0x05, 0x55, 0xaa, 0xd1, 0x01, clamped_percentage],
+            Protocol::Gen2 => vec![0x03, 0x08, clamped_percentage],
+        };
+        self.pad_buffer_to_length(
+            &command_buffer,
+            self.descriptor.reports.feature_report_length,
+        )
+    }
+
+    fn check_key_index(&self, key_index: i32) -> Result<(), StreamDeckError> {
+        if key_index < 0 || key_index >= self.get_num_keys() {
+            return Err(StreamDeckError::InvalidKey(key_index));
+        }
+        Ok(())
+    }
+
+    fn pad_buffer_to_length(&self, input_buffer: &[u8], desired_length: usize) -> Vec<u8> {
         let mut output_buffer: Vec<u8> = vec![0; desired_length];
 
         let len = cmp::min(input_buffer.len(), output_buffer.len());
@@ -162,60 +986,150 @@ impl StreamDeckController {
         output_buffer
     }
 
-    fn set_image_exact(&self, key_index: i32, image: DynamicImage) {
-        let device_key_index = self.convert_key_index(key_index);
+    pub(crate) fn set_image_exact(
+        &self,
+        key_index: i32,
+        image: DynamicImage,
+    ) -> Result<(), StreamDeckError> {
+        self.check_key_index(key_index)?;
+        let device_key_index = u8::try_from(self.to_device_key_index(key_index))
+            .map_err(|_| StreamDeckError::InvalidKey(key_index))?;
+        if !self.descriptor.has_display {
+            return Ok(());
+        }
 
-        let mut page_1_buf: Vec<u8> =
-            vec![0; usize::try_from(Self::NUM_FIRST_PAGE_PIXELS * 3).unwrap()];
-        let mut page_2_buf: Vec<u8> =
-            vec![0; usize::try_from(Self::NUM_SECOND_PAGE_PIXELS * 3).unwrap()];
+        let image = self.fit_to_key(image);
+        let image_hash = hash_image(&image);
+        let key_slot = usize::from(device_key_index);
+        if self.display_state.lock().unwrap().key_image_hashes[key_slot] == Some(image_hash) {
+            return Ok(());
+        }
 
-        // info!("image dims: {}, {}", image.width(), image.height());
+        let image = orient_for_device(
+            self.descriptor,
+            self.get_orientation().to_upright_image(image),
+        );
+        let image_buf = match self.descriptor.image_format {
+            ImageFormat::Bmp => self.encode_bmp(&image),
+            ImageFormat::Jpeg => self.encode_jpeg(&image)?,
+        };
 
-        for pixel_offset in 0..Self::NUM_FIRST_PAGE_PIXELS {
-            // reverse the image
-            let x = image.width() - 1 - (pixel_offset % image.width());
-            let y = pixel_offset / image.width();
-            if x < image.width() && y < image.height() {
-                let pixel = image.get_pixel(x, y);
-                let buffer_offset: usize = usize::try_from(pixel_offset * 3).unwrap();
-                page_1_buf[buffer_offset] = pixel[2]; // B
-                page_1_buf[buffer_offset + 1] = pixel[1]; // G
-                page_1_buf[buffer_offset + 2] = pixel[0]; // R
-            }
+        {
+            let mut display_state = self.display_state.lock().unwrap();
+            display_state.key_images[key_slot] = Some(image_buf.clone());
+            display_state.key_image_hashes[key_slot] = Some(image_hash);
+        }
+        // shown when the deck wakes up
+        if self.is_blanked() {
+            return Ok(());
         }
+        let result = self.write_image_pages(device_key_index, &image_buf);
+        // a lost deck gets the image on reconnect, anything else should be retried
+        if result.is_err() && !matches!(result, Err(StreamDeckError::Disconnected)) {
+            self.display_state.lock().unwrap().key_image_hashes[key_slot] = None;
+        }
+        result
+    }
 
-        for pixel_offset in
-            Self::NUM_FIRST_PAGE_PIXELS..Self::NUM_FIRST_PAGE_PIXELS + Self::NUM_SECOND_PAGE_PIXELS
-        {
-            let x = image.width() - 1 - (pixel_offset % image.width());
-            let y = pixel_offset / image.width();
+    // The deck only shows images of exactly the key's size, so anything else
+    // is centred on a black key, and cropped if it's larger.
+    fn fit_to_key(&self, image: DynamicImage) -> DynamicImage {
+        let icon_size = self.descriptor.icon_size;
+        if image.dimensions() == (icon_size, icon_size) {
+            return image;
+        }
 
-            if x < image.width() && y < image.height() {
-                let pixel = image.get_pixel(x, y);
+        let mut key = ImageBuffer::from_pixel(icon_size, icon_size, Rgb([0, 0, 0]));
+        let x = (i64::from(icon_size) - i64::from(image.width())) / 2;
+        let y = (i64::from(icon_size) - i64::from(image.height())) / 2;
+        image::imageops::overlay(&mut key, &image.to_rgb8(), x, y);
+        DynamicImage::ImageRgb8(key)
+    }
 
-                let buffer_offset: usize =
-                    usize::try_from((pixel_offset - Self::NUM_FIRST_PAGE_PIXELS) * 3).unwrap();
-                page_2_buf[buffer_offset] = pixel[2]; // B
-                page_2_buf[buffer_offset + 1] = pixel[1]; // G
-                page_2_buf[buffer_offset + 2] = pixel[0]; // R
+    fn decode_key_image(&self, image_buf: &[u8]) -> Result<DynamicImage, StreamDeckError> {
+        let format = match self.descriptor.image_format {
+            ImageFormat::Bmp => image::ImageFormat::Bmp,
+            ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+        };
+        Ok(image::load_from_memory_with_format(image_buf, format)?)
+    }
+
+    fn encode_bmp(&self, image: &DynamicImage) -> Vec<u8> {
+        let row_length = image.width() * 3;
+        let row_padding = (4 - row_length % 4) % 4;
+        let pixel_data_length = (row_length + row_padding) * image.height();
+
+        let mut output_buffer =
+            self.get_bmp_header(image.width(), image.height(), pixel_data_length);
+        output_buffer.reserve(usize::try_from(pixel_data_length).unwrap());
+
+        // bmp rows are stored bottom up
+        for y in (0..image.height()).rev() {
+            for x in 0..image.width() {
+                let pixel = image.get_pixel(x, y);
+                output_buffer.push(pixel[2]); // B
+                output_buffer.push(pixel[1]); // G
+                output_buffer.push(pixel[0]); // R
             }
+            output_buffer.resize(
+                output_buffer.len() + usize::try_from(row_padding).unwrap(),
+                0,
+            );
         }
 
-        self.write_page_1(device_key_index, &page_1_buf);
-        self.write_page_2(device_key_index, &page_2_buf);
+        output_buffer
     }
 
-    fn send_feature_report(&self, data: &Vec<u8>) {
-        self.hid_device.send_feature_report(data).unwrap(); // todo: error handling
+    fn encode_jpeg(&self, image: &DynamicImage) -> Result<Vec<u8>, StreamDeckError> {
+        let mut output_buffer: Vec<u8> = vec![];
+        JpegEncoder::new_with_quality(&mut output_buffer, Self::JPEG_QUALITY)
+            .encode_image(&image.to_rgb8())?;
+        Ok(output_buffer)
     }
 
-    fn build_packet(
+    fn get_bmp_header(&self, width: u32, height: u32, pixel_data_length: u32) -> Vec<u8> {
+        let file_length = Self::BMP_HEADER_SIZE + pixel_data_length;
+        let pixels_per_meter: u32 = 3780;
+
+        let mut header: Vec<u8> = vec![0x42, 0x4d];
+        header.extend_from_slice(&file_length.to_le_bytes());
+        header.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        header.extend_from_slice(&Self::BMP_HEADER_SIZE.to_le_bytes());
+        header.extend_from_slice(&40u32.to_le_bytes()); // info header size
+        header.extend_from_slice(&width.to_le_bytes());
+        header.extend_from_slice(&height.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // planes
+        header.extend_from_slice(&24u16.to_le_bytes()); // bits per pixel
+        header.extend_from_slice(&0u32.to_le_bytes()); // no compression
+        header.extend_from_slice(&pixel_data_length.to_le_bytes());
+        header.extend_from_slice(&pixels_per_meter.to_le_bytes());
+        header.extend_from_slice(&pixels_per_meter.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // colors in palette
+        header.extend_from_slice(&0u32.to_le_bytes()); // important colors
+        header
+    }
+
+    fn with_transport<T>(
         &self,
-        header: &Vec<u8>,
-        buffer: &Vec<u8>,
-        padded_buffer_length: usize,
-    ) -> Vec<u8> {
+        f: impl FnOnce(&mut dyn Transport) -> Result<T, StreamDeckError>,
+    ) -> Result<T, StreamDeckError> {
+        let mut transport = self.transport.lock().unwrap();
+        let was_connected = transport.is_connected();
+
+        let result = f(transport.as_mut());
+        if let Err(StreamDeckError::Disconnected) = result {
+            if was_connected {
+                warn!("{} disconnected", self.descriptor.name);
+            }
+        }
+        result
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> Result<(), StreamDeckError> {
+        self.with_transport(|transport| transport.send_feature_report(data))
+    }
+
+    fn build_packet(&self, header: &[u8], buffer: &[u8], padded_buffer_length: usize) -> Vec<u8> {
         let output_buffer_len = header.len() + buffer.len();
         let mut output_buffer: Vec<u8> = vec![0; output_buffer_len];
 
@@ -225,119 +1139,617 @@ impl StreamDeckController {
         self.pad_buffer_to_length(&output_buffer, padded_buffer_length)
     }
 
-    fn write_page_1(&self, key_index: i32, buffer: &Vec<u8>) {
-        self.write(&self.build_packet(
-            &self.get_page_one_header(key_index),
-            buffer,
-            Self::PAGE_PACKET_SIZE,
+    // Splits an encoded image across as many image reports as the model needs.
+    fn write_image_pages(&self, key_index: u8, image_buffer: &[u8]) -> Result<(), StreamDeckError> {
+        let reports = &self.descriptor.reports;
+        let payload_length = reports.image_page_payload_length;
+
+        let num_pages = image_buffer.len().div_ceil(payload_length);
+        for (page_index, page) in image_buffer.chunks(payload_length).enumerate() {
+            let is_last_page = page_index + 1 == num_pages;
+            self.write(&self.build_packet(
+                &self.get_page_header(key_index, page_index, page.len(), is_last_page)?,
+                page,
+                reports.image_report_length,
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn get_page_header(
+        &self,
+        key_index: u8,
+        page_index: usize,
+        page_length: usize,
+        is_last_page: bool,
+    ) -> Result<Vec<u8>, StreamDeckError> {
+        let header = match self.descriptor.reports.protocol {
+            Protocol::Gen1 => vec![
+                0x02,
+                0x01,
+                header_field(page_index + self.descriptor.reports.first_page_number)?,
+                0x00,
+                u8::from(is_last_page),
+                key_index + 1,
+            ],
+            Protocol::Gen2 => {
+                let page_length = header_field::<u16, _>(page_length)?.to_le_bytes();
+                let page_index = header_field::<u16, _>(page_index)?.to_le_bytes();
+                vec![
+                    0x02,
+                    0x07,
+                    key_index,
+                    u8::from(is_last_page),
+                    page_length[0],
+                    page_length[1],
+                    page_index[0],
+                    page_index[1],
+                ]
+            }
+        };
+        Ok(self.pad_buffer_to_length(&header, self.descriptor.reports.image_report_header_length))
+    }
+
+    fn write_touch_strip_pages(
+        &self,
+        area: TouchStripArea,
+        image_buffer: &[u8],
+    ) -> Result<(), StreamDeckError> {
+        let report_length = self.descriptor.reports.image_report_length;
+        let payload_length = report_length - Self::TOUCH_STRIP_HEADER_LENGTH;
+
+        let num_pages = image_buffer.len().div_ceil(payload_length);
+        for (page_index, page) in image_buffer.chunks(payload_length).enumerate() {
+            let is_last_page = page_index + 1 == num_pages;
+            self.write(&self.build_packet(
+                &self.get_touch_strip_page_header(area, page_index, page.len(), is_last_page)?,
+                page,
+                report_length,
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn get_touch_strip_page_header(
+        &self,
+        area: TouchStripArea,
+        page_index: usize,
+        page_length: usize,
+        is_last_page: bool,
+    ) -> Result<Vec<u8>, StreamDeckError> {
+        let mut header: Vec<u8> = vec![0x02, 0x0c];
+        for value in [area.x, area.y, area.width, area.height] {
+            header.extend_from_slice(&header_field::<u16, _>(value)?.to_le_bytes());
+        }
+        header.push(u8::from(is_last_page));
+        header.extend_from_slice(&header_field::<u16, _>(page_index)?.to_le_bytes());
+        header.extend_from_slice(&header_field::<u16, _>(page_length)?.to_le_bytes());
+        Ok(self.pad_buffer_to_length(&header, Self::TOUCH_STRIP_HEADER_LENGTH))
+    }
+
+    fn write(&self, data: &[u8]) -> Result<(), StreamDeckError> {
+        self.with_transport(|transport| transport.write(data))
+    }
+}
+
+// Page numbers, lengths and positions have to fit their report header fields.
+fn header_field<T: TryFrom<U>, U>(value: U) -> Result<T, StreamDeckError> {
+    T::try_from(value).map_err(|_| StreamDeckError::ImageTooLarge)
+}
+
+// The level `step` steps of `num_steps` along from `from` to `to`.  In i64,
+// as a long fade has more steps than i32 can multiply by.
+fn fade_step(from: i32, to: i32, step: i32, num_steps: i32) -> i32 {
+    let (from, to) = (i64::from(from), i64::from(to));
+    let level = from + (to - from) * i64::from(step) / i64::from(num_steps);
+    i32::try_from(level).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_IMAGE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../resources/smiles.png");
+
+    fn mock_deck(model: StreamDeckModel) -> (StreamDeckController, MockTransport) {
+        let transport = MockTransport::new();
+        let controller = StreamDeckController::from_transport(Box::new(transport.clone()), model);
+        (controller, transport)
+    }
+
+    fn padded(bytes: &[u8], length: usize) -> Vec<u8> {
+        let mut report = bytes.to_vec();
+        report.resize(length, 0);
+        report
+    }
+
+    #[test]
+    fn gen1_brightness_report() {
+        let (controller, transport) = mock_deck(StreamDeckModel::OriginalV1);
+        controller.set_brightness(50).unwrap();
+        assert_eq!(
+            transport.feature_reports(),
+            vec![padded(&[0x05, 0x55, 0xaa, 0xd1, 0x01, 50], 17)]
+        );
+    }
+
+    #[test]
+    fn gen2_brightness_report() {
+        let (controller, transport) = mock_deck(StreamDeckModel::Mk2);
+        controller.set_brightness(50).unwrap();
+        assert_eq!(
+            transport.feature_reports(),
+            vec![padded(&[0x03, 0x08, 50], 32)]
+        );
+    }
+
+    #[test]
+    fn original_v1_image_pages() {
+        let (controller, transport) = mock_deck(StreamDeckModel::OriginalV1);
+        controller.fill_color(7, 255, 0, 0).unwrap();
+
+        let pages = transport.written_pages();
+        assert_eq!(pages.len(), 2);
+        for (page_index, page) in pages.iter().enumerate() {
+            assert_eq!(page.len(), 8191);
+            // pages are numbered from 1, and key 7 is in the middle column
+            let is_last = (page_index == 1) as u8;
+            assert_eq!(
+                page[..16],
+                padded(&[0x02, 0x01, page_index as u8 + 1, 0x00, is_last, 8], 16)
+            );
+        }
+        // the 54 byte BMP header and the first 2583 pixels fill the first page
+        assert_eq!(&pages[0][16..18], b"BM");
+        assert_eq!(&pages[0][16 + 54..16 + 54 + 3], &[0, 0, 255]);
+        assert_eq!(&pages[0][16 + 7803 - 3..16 + 7803], &[0, 0, 255]);
+    }
+
+    #[test]
+    fn mini_image_pages() {
+        let (controller, transport) = mock_deck(StreamDeckModel::Mini);
+        controller.fill_color(0, 0, 255, 0).unwrap();
+
+        let icon_size = StreamDeckModel::Mini.descriptor().icon_size as usize;
+        let bmp_length = 54 + icon_size * icon_size * 3;
+        let pages = transport.written_pages();
+        assert_eq!(pages.len(), bmp_length.div_ceil(1024 - 16));
+        for (page_index, page) in pages.iter().enumerate() {
+            assert_eq!(page.len(), 1024);
+            // pages are numbered from 0
+            let is_last = (page_index == pages.len() - 1) as u8;
+            assert_eq!(
+                page[..16],
+                padded(&[0x02, 0x01, page_index as u8, 0x00, is_last, 1], 16)
+            );
+        }
+    }
+
+    #[test]
+    fn gen2_image_pages() {
+        let (controller, transport) = mock_deck(StreamDeckModel::Mk2);
+        controller.set_image(3, TEST_IMAGE).unwrap();
+
+        let pages = transport.written_pages();
+        assert!(!pages.is_empty());
+        let mut jpeg = vec![];
+        for (page_index, page) in pages.iter().enumerate() {
+            assert_eq!(page.len(), 1024);
+            let is_last = (page_index == pages.len() - 1) as u8;
+            let length = u16::from_le_bytes([page[4], page[5]]) as usize;
+            assert_eq!(page[..4], [0x02, 0x07, 3, is_last]);
+            assert_eq!(u16::from_le_bytes([page[6], page[7]]) as usize, page_index);
+            if is_last == 0 {
+                assert_eq!(length, 1024 - 8);
+            }
+            jpeg.extend_from_slice(&page[8..8 + length]);
+        }
+
+        let image = image::load_from_memory(&jpeg).unwrap();
+        let icon_size = StreamDeckModel::Mk2.descriptor().icon_size;
+        assert_eq!(image.dimensions(), (icon_size, icon_size));
+    }
+
+    fn written_key_image(transport: &MockTransport, model: StreamDeckModel) -> DynamicImage {
+        let header_length = model.descriptor().reports.image_report_header_length;
+        let mut image_buf = vec![];
+        for page in transport.written_pages() {
+            let length = usize::from(u16::from_le_bytes([page[4], page[5]]));
+            image_buf.extend_from_slice(&page[header_length..header_length + length]);
+        }
+        image::load_from_memory(&image_buf).unwrap()
+    }
+
+    #[test]
+    fn images_are_sent_at_the_key_size() {
+        let model = StreamDeckModel::Mk2;
+        let icon_size = model.descriptor().icon_size;
+        let blank = concat!(env!("CARGO_MANIFEST_DIR"), "/../resources/blank.png");
+        let wide = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(40, 20, Rgb([255, 255, 255])));
+        let large = DynamicImage::ImageRgb8(ImageBuffer::new(icon_size + 9, icon_size));
+
+        let (controller, transport) = mock_deck(model);
+        controller.set_image(0, blank).unwrap();
+        assert_eq!(
+            written_key_image(&transport, model).dimensions(),
+            (icon_size, icon_size)
+        );
+
+        for orientation in [Rotation::Rotate0, Rotation::Rotate90] {
+            controller.set_orientation(Orientation::new(orientation));
+            for image in [&wide, &large] {
+                transport.clear();
+                controller.set_image_exact(1, image.clone()).unwrap();
+                let written = written_key_image(&transport, model);
+                assert_eq!(written.dimensions(), (icon_size, icon_size));
+            }
+        }
+
+        // the wide image is centred, with black above and below
+        controller.set_orientation(Orientation::default());
+        transport.clear();
+        controller.set_image_exact(2, wide).unwrap();
+        let written = written_key_image(&transport, model).to_rgb8();
+        let centre = icon_size / 2;
+        assert!(written.get_pixel(centre, centre)[0] > 200);
+        assert!(written.get_pixel(centre, 2)[0] < 50);
+        assert!(written.get_pixel(2, centre)[0] < 50);
+    }
+
+    #[test]
+    fn original_v1_reverses_key_columns() {
+        let (controller, transport) = mock_deck(StreamDeckModel::OriginalV1);
+        // (logical key, device key) for the corners and the middle
+        for (key, device_key) in [(0, 4), (4, 0), (7, 7), (10, 14), (14, 10)] {
+            transport.clear();
+            controller.fill_color(key, 0, 0, key as u8).unwrap();
+            assert_eq!(transport.written_pages()[0][5], device_key + 1);
+        }
+    }
+
+    #[test]
+    fn original_v1_reverses_key_columns_in_events() {
+        let (controller, transport) = mock_deck(StreamDeckModel::OriginalV1);
+        let offset = StreamDeckModel::OriginalV1
+            .descriptor()
+            .reports
+            .key_state_offset;
+        let mut report = vec![0u8; offset + 15];
+        report[0] = 0x01;
+        // device key 0 is the top right key
+        report[offset] = 1;
+        transport.push_input_report(&report);
+
+        let events = controller.read_events().unwrap();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            DeckEvent::Key(KeyEvent {
+                key: 4,
+                kind: KeyEventKind::Down
+            })
+        )));
+    }
+
+    // Feeds one raw 14 byte Plus input report through `read_events`.
+    fn plus_events(report: [u8; 14]) -> Vec<DeckEvent> {
+        let (controller, transport) = mock_deck(StreamDeckModel::Plus);
+        transport.push_input_report(&report);
+        controller
+            .read_events_timeout(time::Duration::from_millis(1))
+            .unwrap()
+    }
+
+    fn dial(dial: i32, kind: DialEventKind) -> DeckEvent {
+        DeckEvent::Dial(DialEvent { dial, kind })
+    }
+
+    #[test]
+    fn plus_dial_rotation() {
+        let cases = [
+            (
+                [
+                    0x01, 0x03, 0x05, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0,
+                ],
+                vec![dial(0, DialEventKind::Rotate(1))],
+            ),
+            (
+                [
+                    0x01, 0x03, 0x05, 0x00, 0x01, 0x00, 0xfd, 0x00, 0x04, 0, 0, 0, 0, 0,
+                ],
+                vec![
+                    dial(1, DialEventKind::Rotate(-3)),
+                    dial(3, DialEventKind::Rotate(4)),
+                ],
+            ),
+            (
+                [
+                    0x01, 0x03, 0x05, 0x00, 0x01, 0x00, 0x00, 0x80, 0x7f, 0, 0, 0, 0, 0,
+                ],
+                vec![
+                    dial(2, DialEventKind::Rotate(-128)),
+                    dial(3, DialEventKind::Rotate(127)),
+                ],
+            ),
+        ];
+        for (report, expected) in cases {
+            assert_eq!(plus_events(report), expected, "{:02x?}", report);
+        }
+    }
+
+    #[test]
+    fn plus_dial_press_and_release() {
+        let (controller, transport) = mock_deck(StreamDeckModel::Plus);
+        let read = |dials: [u8; 4]| {
+            let mut report = [0u8; 14];
+            report[..5].copy_from_slice(&[0x01, 0x03, 0x05, 0x00, 0x00]);
+            report[5..9].copy_from_slice(&dials);
+            transport.push_input_report(&report);
+            controller
+                .read_events_timeout(time::Duration::from_millis(1))
+                .unwrap()
+        };
+
+        assert_eq!(read([0, 1, 0, 0]), [dial(1, DialEventKind::Down)]);
+        // still held, so nothing new
+        assert_eq!(read([0, 1, 0, 0]), []);
+        assert_eq!(
+            read([1, 0, 0, 0]),
+            [dial(0, DialEventKind::Down), dial(1, DialEventKind::Up)]
+        );
+        assert_eq!(read([0, 0, 0, 0]), [dial(0, DialEventKind::Up)]);
+    }
+
+    #[test]
+    fn plus_touch_strip() {
+        let touch = |x, y, kind| vec![DeckEvent::Touch(TouchEvent { x, y, kind })];
+        let cases = [
+            (
+                [
+                    0x01, 0x02, 0x0e, 0x00, 0x01, 0x00, 0x2c, 0x01, 0x32, 0x00, 0, 0, 0, 0,
+                ],
+                touch(300, 50, TouchEventKind::Tap),
+            ),
+            (
+                [
+                    0x01, 0x02, 0x0e, 0x00, 0x02, 0x00, 0x0a, 0x00, 0x05, 0x00, 0, 0, 0, 0,
+                ],
+                touch(10, 5, TouchEventKind::LongPress),
+            ),
+            (
+                [
+                    0x01, 0x02, 0x0e, 0x00, 0x03, 0x00, 0x64, 0x00, 0x14, 0x00, 0x20, 0x03, 0x5a,
+                    0x00,
+                ],
+                touch(
+                    100,
+                    20,
+                    TouchEventKind::Swipe {
+                        to_x: 800,
+                        to_y: 90,
+                    },
+                ),
+            ),
+            (
+                [
+                    0x01, 0x02, 0x0e, 0x00, 0x07, 0x00, 0x64, 0x00, 0x14, 0x00, 0, 0, 0, 0,
+                ],
+                vec![],
+            ),
+        ];
+        for (report, expected) in cases {
+            assert_eq!(plus_events(report), expected, "{:02x?}", report);
+        }
+    }
+
+    #[test]
+    fn touch_strip_area_past_u32_max_is_out_of_bounds() {
+        let (controller, transport) = mock_deck(StreamDeckModel::Plus);
+        let image = DynamicImage::ImageRgb8(ImageBuffer::new(10, 10));
+        for (x, y) in [(u32::MAX - 5, 0), (0, u32::MAX - 5)] {
+            assert!(matches!(
+                controller.set_touch_strip_area(x, y, image.clone()),
+                Err(StreamDeckError::InvalidTouchStripArea { .. })
+            ));
+        }
+        assert!(transport.written_pages().is_empty());
+    }
+
+    // The key each Gen2 image page is for.
+    fn gen2_page_keys(transport: &MockTransport) -> Vec<u8> {
+        let mut keys: Vec<u8> = transport
+            .written_pages()
+            .iter()
+            .map(|page| page[2])
+            .collect();
+        keys.dedup();
+        keys
+    }
+
+    #[test]
+    fn replays_the_display_after_a_reconnect() {
+        let (controller, transport) = mock_deck(StreamDeckModel::Mk2);
+        controller.set_brightness(40).unwrap();
+        controller.fill_color(0, 255, 0, 0).unwrap();
+
+        transport.set_plugged_in(false);
+        assert!(matches!(
+            controller.fill_color(1, 0, 255, 0),
+            Err(StreamDeckError::Disconnected)
+        ));
+        controller
+            .read_events_timeout(time::Duration::from_millis(1))
+            .unwrap();
+        assert!(!controller.is_connected());
+
+        transport.clear();
+        transport.set_plugged_in(true);
+        controller
+            .read_events_timeout(time::Duration::from_millis(1))
+            .unwrap();
+        assert!(controller.is_connected());
+        assert_eq!(
+            transport.feature_reports(),
+            vec![padded(&[0x03, 0x08, 40], 32)]
+        );
+        // including the image set while the deck was away
+        assert_eq!(gen2_page_keys(&transport), [0, 1]);
+    }
+
+    #[test]
+    fn reads_serial_number_and_firmware_version() {
+        let (controller, transport) = mock_deck(StreamDeckModel::OriginalV1);
+        transport.set_feature_report_response(0x03, b"\x03\0\0\0\0AL12H1A00001\0\xff");
+        transport.set_feature_report_response(0x04, b"\x04\0\0\0\x001.0.170133\0");
+        assert_eq!(controller.get_serial_number().unwrap(), "AL12H1A00001");
+        assert_eq!(controller.get_firmware_version().unwrap(), "1.0.170133");
+
+        let (controller, transport) = mock_deck(StreamDeckModel::Mk2);
+        transport.set_feature_report_response(0x06, b"\x06\x0cDL49K1A69132");
+        transport.set_feature_report_response(0x05, b"\x05\0\0\0\0\x001.01.000");
+        assert_eq!(controller.get_serial_number().unwrap(), "DL49K1A69132");
+        assert_eq!(controller.get_firmware_version().unwrap(), "1.01.000");
+
+        // nothing set reads back as empty
+        let (controller, _) = mock_deck(StreamDeckModel::Mini);
+        assert_eq!(controller.get_serial_number().unwrap(), "");
+    }
+
+    #[test]
+    fn reset_sends_the_reset_report_and_forgets_the_images() {
+        let (controller, transport) = mock_deck(StreamDeckModel::OriginalV1);
+        controller.reset().unwrap();
+        assert_eq!(transport.feature_reports(), vec![padded(&[0x0b, 0x63], 17)]);
+
+        let (controller, transport) = mock_deck(StreamDeckModel::Mk2);
+        controller.fill_color(3, 0, 0, 255).unwrap();
+        controller.reset().unwrap();
+        assert_eq!(transport.feature_reports(), vec![padded(&[0x03, 0x02], 32)]);
+
+        // the key was cleared by the reset, so the same image is sent again
+        transport.clear();
+        controller.fill_color(3, 0, 0, 255).unwrap();
+        assert_eq!(gen2_page_keys(&transport), [3]);
+
+        // and a reconnect only brings back what was set since
+        transport.clear();
+        transport.set_plugged_in(false);
+        transport.set_plugged_in(true);
+        controller.reconnect().unwrap();
+        assert_eq!(gen2_page_keys(&transport), [3]);
+    }
+
+    #[test]
+    fn dimming_never_brightens_the_deck() {
+        let (controller, transport) = mock_deck(StreamDeckModel::Mk2);
+        controller.set_brightness(20).unwrap();
+        controller
+            .set_idle_timeout(Some(time::Duration::ZERO), IdleMode::Dim(50))
+            .unwrap();
+
+        transport.clear();
+        controller
+            .read_events_timeout(time::Duration::from_millis(1))
+            .unwrap();
+        assert!(controller.is_idle());
+        assert!(transport
+            .feature_reports()
+            .iter()
+            .all(|report| report[2] <= 20));
+    }
+
+    #[test]
+    fn turning_the_idle_timeout_off_wakes_the_deck() {
+        let (controller, transport) = mock_deck(StreamDeckModel::Mk2);
+        controller.set_brightness(60).unwrap();
+        controller.fill_color(0, 255, 0, 0).unwrap();
+        controller
+            .set_idle_timeout(Some(time::Duration::ZERO), IdleMode::Blank)
+            .unwrap();
+        controller
+            .read_events_timeout(time::Duration::from_millis(1))
+            .unwrap();
+        assert!(controller.is_idle());
+
+        transport.clear();
+        controller.set_idle_timeout(None, IdleMode::Blank).unwrap();
+        assert!(!controller.is_idle());
+        assert_eq!(gen2_page_keys(&transport), [0]);
+        assert_eq!(
+            transport.feature_reports().last(),
+            Some(&padded(&[0x03, 0x08, 60], 32))
+        );
+    }
+
+    #[test]
+    fn fade_steps_do_not_overflow() {
+        assert_eq!(fade_step(0, 100, 1, 4), 25);
+        assert_eq!(fade_step(100, 0, 3, 4), 25);
+        assert_eq!(fade_step(0, 100, 4, 4), 100);
+        assert_eq!(fade_step(0, 100, i32::MAX / 2, i32::MAX), 49);
+        assert_eq!(fade_step(100, 5, i32::MAX, i32::MAX), 5);
+        assert_eq!(fade_step(i32::MIN, i32::MAX, 1, 1), i32::MAX);
+    }
+
+    #[test]
+    fn missing_hardware_has_its_own_errors() {
+        let (pedal, _) = mock_deck(StreamDeckModel::Pedal);
+        assert!(matches!(
+            pedal.render_snapshot(0),
+            Err(StreamDeckError::NoDisplay("Stream Deck Pedal"))
+        ));
+
+        let (controller, _) = mock_deck(StreamDeckModel::Mk2);
+        let image = DynamicImage::ImageRgb8(ImageBuffer::new(10, 10));
+        assert!(matches!(
+            controller.set_touch_strip_area(0, 0, image),
+            Err(StreamDeckError::NoTouchStrip(_))
         ));
     }
 
-    fn write_page_2(&self, key_index: i32, buffer: &Vec<u8>) {
-        self.write(&self.build_packet(
-            &self.get_page_two_header(key_index),
-            buffer,
-            Self::PAGE_PACKET_SIZE,
+    #[test]
+    fn page_numbers_must_fit_the_header() {
+        assert_eq!(header_field::<u8, usize>(255).unwrap(), 255);
+        assert!(matches!(
+            header_field::<u8, usize>(256),
+            Err(StreamDeckError::ImageTooLarge)
+        ));
+        assert!(matches!(
+            header_field::<u16, u32>(70_000),
+            Err(StreamDeckError::ImageTooLarge)
         ));
     }
 
-    fn get_page_one_header(&self, key_index: i32) -> Vec<u8> {
-        vec![
-            0x02,
-            0x01,
-            0x01,
-            0x00,
-            0x00,
-            u8::try_from(key_index + 1).unwrap(),
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x42,
-            0x4d,
-            0xf6,
-            0x3c,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x36,
-            0x00,
-            0x00,
-            0x00,
-            0x28,
-            0x00,
-            0x00,
-            0x00,
-            0x48,
-            0x00,
-            0x00,
-            0x00,
-            0x48,
-            0x00,
-            0x00,
-            0x00,
-            0x01,
-            0x00,
-            0x18,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0xc0,
-            0x3c,
-            0x00,
-            0x00,
-            0xc4,
-            0x0e,
-            0x00,
-            0x00,
-            0xc4,
-            0x0e,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-        ]
-    }
-
-    fn get_page_two_header(&self, key_index: i32) -> Vec<u8> {
-        vec![
-            0x02,
-            0x01,
-            0x02,
-            0x00,
-            0x01,
-            u8::try_from(key_index + 1).unwrap(),
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-        ]
-    }
-
-    fn write(&self, data: &[u8]) {
-        self.hid_device.write(data).unwrap();
+    #[test]
+    fn key_gap_too_large_is_an_error() {
+        let (controller, _) = mock_deck(StreamDeckModel::Mk2);
+        controller.fill_color(0, 255, 0, 0).unwrap();
+        let snapshot = controller.render_snapshot(10).unwrap();
+        assert_eq!(snapshot.dimensions(), (5 * 72 + 4 * 10, 3 * 72 + 2 * 10));
+
+        for key_gap in [u32::MAX / 2, u32::MAX] {
+            assert!(matches!(
+                controller.render_snapshot(key_gap),
+                Err(StreamDeckError::InvalidKeyGap(_))
+            ));
+            assert!(matches!(
+                controller.set_panel_image(TEST_IMAGE, key_gap),
+                Err(StreamDeckError::InvalidKeyGap(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn redundant_image_is_not_sent() {
+        let (controller, transport) = mock_deck(StreamDeckModel::Mk2);
+        controller.set_image(0, TEST_IMAGE).unwrap();
+        assert!(!transport.written_pages().is_empty());
+
+        transport.clear();
+        controller.set_image(0, TEST_IMAGE).unwrap();
+        assert!(transport.written_pages().is_empty());
+
+        // another key still gets its own copy
+        controller.set_image(1, TEST_IMAGE).unwrap();
+        assert!(!transport.written_pages().is_empty());
     }
 }
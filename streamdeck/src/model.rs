@@ -0,0 +1,287 @@
+// Model details found here:
+// https://github.com/abcminiuser/python-elgato-streamdeck/tree/master/src/StreamDeck/Devices
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamDeckModel {
+    OriginalV1,
+    OriginalV2,
+    Mk2,
+    Mini,
+    MiniMk2,
+    Xl,
+    XlV2,
+    Neo,
+    Plus,
+    Pedal,
+}
+
+/// Encoding the device expects for key images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Bmp,
+    Jpeg,
+}
+
+/// Clockwise rotation applied to an image before it is sent to the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+/// Which family of HID reports the device speaks.
+///
+/// Gen1 covers the original v1 deck and the Minis (16 byte image page headers,
+/// 17 byte feature reports).  Gen2 covers everything newer (8 byte image page
+/// headers, 32 byte feature reports).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Gen1,
+    Gen2,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReportLayout {
+    pub protocol: Protocol,
+    /// Offset of the first key state byte in an input report.
+    pub key_state_offset: usize,
+    /// Total length of a single image page write, header included.
+    pub image_report_length: usize,
+    pub image_report_header_length: usize,
+    /// Most image bytes sent in one page.  Up to the report length less the
+    /// header; any room left over is zero padded.
+    pub image_page_payload_length: usize,
+    /// Number of the first image page, which Gen1 models disagree on.
+    pub first_page_number: usize,
+    pub feature_report_length: usize,
+}
+
+#[derive(Debug)]
+pub struct ModelDescriptor {
+    pub model: StreamDeckModel,
+    pub name: &'static str,
+    pub product_id: u16,
+    pub key_count: i32,
+    pub columns: i32,
+    pub rows: i32,
+    /// False for input only devices like the Pedal, which ignore image and
+    /// brightness calls.
+    pub has_display: bool,
+    pub icon_size: u32,
+    pub image_format: ImageFormat,
+    pub rotation: Rotation,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    /// The original v1 deck numbers its keys right to left.
+    pub reversed_key_columns: bool,
+    /// Rotary encoders below the keys (Stream Deck Plus).
+    pub dial_count: i32,
+    /// Width and height of the LCD touch strip, if the model has one.
+    pub touch_strip_size: Option<(u32, u32)>,
+    pub reports: ReportLayout,
+}
+
+const GEN1_REPORTS: ReportLayout = ReportLayout {
+    protocol: Protocol::Gen1,
+    key_state_offset: 1,
+    image_report_length: 8191,
+    image_report_header_length: 16,
+    // the BMP split evenly over two pages, as the v1 has always been sent
+    image_page_payload_length: 7803,
+    first_page_number: 1,
+    feature_report_length: 17,
+};
+
+const GEN1_MINI_REPORTS: ReportLayout = ReportLayout {
+    image_report_length: 1024,
+    image_page_payload_length: 1024 - 16,
+    first_page_number: 0,
+    ..GEN1_REPORTS
+};
+
+const GEN2_REPORTS: ReportLayout = ReportLayout {
+    protocol: Protocol::Gen2,
+    key_state_offset: 4,
+    image_report_length: 1024,
+    image_report_header_length: 8,
+    image_page_payload_length: 1024 - 8,
+    first_page_number: 0,
+    feature_report_length: 32,
+};
+
+const ORIGINAL_V1: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::OriginalV1,
+    name: "Stream Deck Original",
+    product_id: 0x0060,
+    key_count: 15,
+    columns: 5,
+    rows: 3,
+    has_display: true,
+    icon_size: 72,
+    image_format: ImageFormat::Bmp,
+    rotation: Rotation::Rotate0,
+    flip_horizontal: true,
+    flip_vertical: true,
+    reversed_key_columns: true,
+    dial_count: 0,
+    touch_strip_size: None,
+    reports: GEN1_REPORTS,
+};
+
+const ORIGINAL_V2: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::OriginalV2,
+    name: "Stream Deck Original (V2)",
+    product_id: 0x006d,
+    image_format: ImageFormat::Jpeg,
+    reversed_key_columns: false,
+    reports: GEN2_REPORTS,
+    ..ORIGINAL_V1
+};
+
+const MK2: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::Mk2,
+    name: "Stream Deck MK.2",
+    product_id: 0x0080,
+    ..ORIGINAL_V2
+};
+
+const MINI: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::Mini,
+    name: "Stream Deck Mini",
+    product_id: 0x0063,
+    key_count: 6,
+    columns: 3,
+    rows: 2,
+    has_display: true,
+    icon_size: 80,
+    image_format: ImageFormat::Bmp,
+    rotation: Rotation::Rotate270,
+    flip_horizontal: false,
+    flip_vertical: true,
+    reversed_key_columns: false,
+    dial_count: 0,
+    touch_strip_size: None,
+    reports: GEN1_MINI_REPORTS,
+};
+
+const MINI_MK2: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::MiniMk2,
+    name: "Stream Deck Mini MK.2",
+    product_id: 0x0090,
+    ..MINI
+};
+
+const XL: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::Xl,
+    name: "Stream Deck XL",
+    product_id: 0x006c,
+    key_count: 32,
+    columns: 8,
+    rows: 4,
+    has_display: true,
+    icon_size: 96,
+    image_format: ImageFormat::Jpeg,
+    rotation: Rotation::Rotate0,
+    flip_horizontal: true,
+    flip_vertical: true,
+    reversed_key_columns: false,
+    dial_count: 0,
+    touch_strip_size: None,
+    reports: GEN2_REPORTS,
+};
+
+const XL_V2: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::XlV2,
+    name: "Stream Deck XL (V2)",
+    product_id: 0x008f,
+    ..XL
+};
+
+const NEO: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::Neo,
+    name: "Stream Deck Neo",
+    product_id: 0x009a,
+    key_count: 8,
+    columns: 4,
+    rows: 2,
+    ..XL
+};
+
+const PLUS: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::Plus,
+    name: "Stream Deck +",
+    product_id: 0x0084,
+    key_count: 8,
+    columns: 4,
+    rows: 2,
+    has_display: true,
+    icon_size: 120,
+    image_format: ImageFormat::Jpeg,
+    rotation: Rotation::Rotate0,
+    flip_horizontal: false,
+    flip_vertical: false,
+    reversed_key_columns: false,
+    dial_count: 4,
+    touch_strip_size: Some((800, 100)),
+    reports: GEN2_REPORTS,
+};
+
+const PEDAL: ModelDescriptor = ModelDescriptor {
+    model: StreamDeckModel::Pedal,
+    name: "Stream Deck Pedal",
+    product_id: 0x0086,
+    key_count: 3,
+    columns: 3,
+    rows: 1,
+    has_display: false,
+    icon_size: 0,
+    image_format: ImageFormat::Jpeg,
+    rotation: Rotation::Rotate0,
+    flip_horizontal: false,
+    flip_vertical: false,
+    reversed_key_columns: false,
+    dial_count: 0,
+    touch_strip_size: None,
+    reports: GEN2_REPORTS,
+};
+
+const ALL_MODELS: [&ModelDescriptor; 10] = [
+    &ORIGINAL_V1,
+    &ORIGINAL_V2,
+    &MK2,
+    &MINI,
+    &MINI_MK2,
+    &XL,
+    &XL_V2,
+    &NEO,
+    &PLUS,
+    &PEDAL,
+];
+
+impl StreamDeckModel {
+    pub const VENDOR_ID: u16 = 0x0fd9;
+
+    pub fn from_product_id(product_id: u16) -> Option<StreamDeckModel> {
+        ALL_MODELS
+            .iter()
+            .find(|descriptor| descriptor.product_id == product_id)
+            .map(|descriptor| descriptor.model)
+    }
+
+    pub fn descriptor(&self) -> &'static ModelDescriptor {
+        match self {
+            StreamDeckModel::OriginalV1 => &ORIGINAL_V1,
+            StreamDeckModel::OriginalV2 => &ORIGINAL_V2,
+            StreamDeckModel::Mk2 => &MK2,
+            StreamDeckModel::Mini => &MINI,
+            StreamDeckModel::MiniMk2 => &MINI_MK2,
+            StreamDeckModel::Xl => &XL,
+            StreamDeckModel::XlV2 => &XL_V2,
+            StreamDeckModel::Neo => &NEO,
+            StreamDeckModel::Plus => &PLUS,
+            StreamDeckModel::Pedal => &PEDAL,
+        }
+    }
+}
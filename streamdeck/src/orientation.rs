@@ -0,0 +1,269 @@
+use image::DynamicImage;
+
+use crate::{ModelDescriptor, Rotation};
+
+/// How a deck is mounted.  Key images are turned so they look upright and key
+/// indexes are renumbered left to right, top to bottom as seen by the user.
+///
+/// `rotation` is how far the deck is turned clockwise from its normal upright
+/// position.  The flips mirror the layout after rotating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Orientation::new(Rotation::Rotate0)
+    }
+}
+
+impl Orientation {
+    pub fn new(rotation: Rotation) -> Orientation {
+        Orientation {
+            rotation,
+            flip_horizontal: false,
+            flip_vertical: false,
+        }
+    }
+
+    /// Columns and rows of a `columns` x `rows` deck as seen by the user.
+    pub fn logical_size(&self, columns: i32, rows: i32) -> (i32, i32) {
+        match self.rotation {
+            Rotation::Rotate0 | Rotation::Rotate180 => (columns, rows),
+            Rotation::Rotate90 | Rotation::Rotate270 => (rows, columns),
+        }
+    }
+
+    /// Maps a key index on the upright deck to the index the user sees.
+    pub(crate) fn to_logical_key_index(self, key_index: i32, columns: i32, rows: i32) -> i32 {
+        let (x, y) = (key_index % columns, key_index / columns);
+        let (x, y) = match self.rotation {
+            Rotation::Rotate0 => (x, y),
+            Rotation::Rotate90 => (rows - 1 - y, x),
+            Rotation::Rotate180 => (columns - 1 - x, rows - 1 - y),
+            Rotation::Rotate270 => (y, columns - 1 - x),
+        };
+
+        let (logical_columns, logical_rows) = self.logical_size(columns, rows);
+        let x = if self.flip_horizontal {
+            logical_columns - 1 - x
+        } else {
+            x
+        };
+        let y = if self.flip_vertical {
+            logical_rows - 1 - y
+        } else {
+            y
+        };
+        y * logical_columns + x
+    }
+
+    /// The inverse of `to_logical_key_index`.
+    pub(crate) fn to_upright_key_index(self, key_index: i32, columns: i32, rows: i32) -> i32 {
+        let (logical_columns, logical_rows) = self.logical_size(columns, rows);
+        let (x, y) = (key_index % logical_columns, key_index / logical_columns);
+        let x = if self.flip_horizontal {
+            logical_columns - 1 - x
+        } else {
+            x
+        };
+        let y = if self.flip_vertical {
+            logical_rows - 1 - y
+        } else {
+            y
+        };
+
+        let (x, y) = match self.rotation {
+            Rotation::Rotate0 => (x, y),
+            Rotation::Rotate90 => (y, rows - 1 - x),
+            Rotation::Rotate180 => (columns - 1 - x, rows - 1 - y),
+            Rotation::Rotate270 => (columns - 1 - y, x),
+        };
+        y * columns + x
+    }
+
+    /// Turns an image drawn for the user's view into one for the upright deck.
+    pub(crate) fn to_upright_image(self, image: DynamicImage) -> DynamicImage {
+        let mut image = image;
+        if self.flip_horizontal {
+            image = image.fliph();
+        }
+        if self.flip_vertical {
+            image = image.flipv();
+        }
+        unrotate_image(image, self.rotation)
+    }
+
+    /// The inverse of `to_upright_image`.
+    pub(crate) fn to_logical_image(self, image: DynamicImage) -> DynamicImage {
+        let mut image = rotate_image(image, self.rotation);
+        if self.flip_horizontal {
+            image = image.fliph();
+        }
+        if self.flip_vertical {
+            image = image.flipv();
+        }
+        image
+    }
+}
+
+/// Rotates an image clockwise.
+pub(crate) fn rotate_image(image: DynamicImage, rotation: Rotation) -> DynamicImage {
+    match rotation {
+        Rotation::Rotate0 => image,
+        Rotation::Rotate90 => image.rotate90(),
+        Rotation::Rotate180 => image.rotate180(),
+        Rotation::Rotate270 => image.rotate270(),
+    }
+}
+
+/// Undoes `rotate_image`.
+pub(crate) fn unrotate_image(image: DynamicImage, rotation: Rotation) -> DynamicImage {
+    match rotation {
+        Rotation::Rotate0 => image,
+        Rotation::Rotate90 => image.rotate270(),
+        Rotation::Rotate180 => image.rotate180(),
+        Rotation::Rotate270 => image.rotate90(),
+    }
+}
+
+/// Rotates and flips an upright key image the way the model expects it.
+pub(crate) fn orient_for_device(descriptor: &ModelDescriptor, image: DynamicImage) -> DynamicImage {
+    let mut image = rotate_image(image, descriptor.rotation);
+    if descriptor.flip_horizontal {
+        image = image.fliph();
+    }
+    if descriptor.flip_vertical {
+        image = image.flipv();
+    }
+    image
+}
+
+/// The inverse of `orient_for_device`, for showing what was sent to a key.
+pub(crate) fn unorient_from_device(
+    descriptor: &ModelDescriptor,
+    image: DynamicImage,
+) -> DynamicImage {
+    let mut image = image;
+    if descriptor.flip_vertical {
+        image = image.flipv();
+    }
+    if descriptor.flip_horizontal {
+        image = image.fliph();
+    }
+    unrotate_image(image, descriptor.rotation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, ImageBuffer, Luma};
+
+    const COLUMNS: i32 = 5;
+    const ROWS: i32 = 3;
+
+    fn all_orientations() -> Vec<Orientation> {
+        let rotations = [
+            Rotation::Rotate0,
+            Rotation::Rotate90,
+            Rotation::Rotate180,
+            Rotation::Rotate270,
+        ];
+        let mut orientations = vec![];
+        for rotation in rotations {
+            for flip_horizontal in [false, true] {
+                for flip_vertical in [false, true] {
+                    orientations.push(Orientation {
+                        rotation,
+                        flip_horizontal,
+                        flip_vertical,
+                    });
+                }
+            }
+        }
+        orientations
+    }
+
+    // One pixel per key of the upright deck, holding that key's index.
+    fn upright_key_image() -> DynamicImage {
+        DynamicImage::ImageLuma8(ImageBuffer::from_fn(COLUMNS as u32, ROWS as u32, |x, y| {
+            Luma([(y * COLUMNS as u32 + x) as u8])
+        }))
+    }
+
+    #[test]
+    fn key_indexes_round_trip() {
+        for orientation in all_orientations() {
+            let mut seen = vec![false; (COLUMNS * ROWS) as usize];
+            for key in 0..COLUMNS * ROWS {
+                let logical = orientation.to_logical_key_index(key, COLUMNS, ROWS);
+                assert!(
+                    (0..COLUMNS * ROWS).contains(&logical),
+                    "{:?} moved key {} off the deck",
+                    orientation,
+                    key
+                );
+                assert!(
+                    !seen[logical as usize],
+                    "{:?} reused key {}",
+                    orientation, logical
+                );
+                seen[logical as usize] = true;
+                assert_eq!(
+                    orientation.to_upright_key_index(logical, COLUMNS, ROWS),
+                    key,
+                    "{:?}",
+                    orientation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let image = upright_key_image();
+        for orientation in all_orientations() {
+            let logical = orientation.to_logical_image(image.clone());
+            assert_eq!(
+                orientation.to_upright_image(logical.clone()).as_bytes(),
+                image.as_bytes(),
+                "{:?}",
+                orientation
+            );
+            assert_eq!(
+                orientation.to_logical_image(orientation.to_upright_image(logical.clone())),
+                logical,
+                "{:?}",
+                orientation
+            );
+        }
+    }
+
+    #[test]
+    fn images_turn_with_key_indexes() {
+        let image = upright_key_image();
+        for orientation in all_orientations() {
+            let logical = orientation.to_logical_image(image.clone());
+            let (logical_columns, logical_rows) = orientation.logical_size(COLUMNS, ROWS);
+            assert_eq!(
+                logical.dimensions(),
+                (logical_columns as u32, logical_rows as u32)
+            );
+            for key in 0..COLUMNS * ROWS {
+                let logical_key = orientation.to_logical_key_index(key, COLUMNS, ROWS);
+                let x = (logical_key % logical_columns) as u32;
+                let y = (logical_key / logical_columns) as u32;
+                assert_eq!(
+                    logical.get_pixel(x, y)[0] as i32,
+                    key,
+                    "{:?} put key {} somewhere else",
+                    orientation,
+                    key
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageBuffer, Rgb, RgbImage};
+
+use crate::StreamDeckError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HorizontalAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerticalAlignment {
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// How to draw a text label onto a key.
+#[derive(Clone)]
+pub struct TextStyle {
+    pub font: FontArc,
+    /// Line height in pixels.
+    pub size: f32,
+    pub color: [u8; 3],
+    pub horizontal_alignment: HorizontalAlignment,
+    pub vertical_alignment: VerticalAlignment,
+    pub background_color: [u8; 3],
+    /// Drawn over the background color, scaled to fill the key.
+    pub background_image: Option<DynamicImage>,
+}
+
+impl TextStyle {
+    const PADDING: f32 = 4.0;
+
+    /// White text centered on black.
+    pub fn new(font: FontArc, size: f32) -> TextStyle {
+        TextStyle {
+            font,
+            size,
+            color: [255, 255, 255],
+            horizontal_alignment: HorizontalAlignment::Center,
+            vertical_alignment: VerticalAlignment::Middle,
+            background_color: [0, 0, 0],
+            background_image: None,
+        }
+    }
+
+    /// Loads a TrueType / OpenType font for use in a `TextStyle`.
+    pub fn load_font(font_file_path: &str) -> Result<FontArc, StreamDeckError> {
+        let font_data = std::fs::read(font_file_path)
+            .map_err(|e| StreamDeckError::Font(format!("{}: {}", font_file_path, e)))?;
+        FontArc::try_from_vec(font_data)
+            .map_err(|e| StreamDeckError::Font(format!("{}: {}", font_file_path, e)))
+    }
+}
+
+/// Draws `text` (one line per `\n`) into a square image `icon_size` pixels wide.
+pub(crate) fn render_text(icon_size: u32, text: &str, style: &TextStyle) -> RgbImage {
+    let mut image: RgbImage =
+        ImageBuffer::from_pixel(icon_size, icon_size, Rgb(style.background_color));
+
+    if let Some(background_image) = &style.background_image {
+        let background = background_image
+            .resize_to_fill(icon_size, icon_size, FilterType::Triangle)
+            .to_rgba8();
+        for (x, y, pixel) in background.enumerate_pixels() {
+            let alpha = f32::from(pixel[3]) / 255.0;
+            let target = image.get_pixel_mut(x, y);
+            for channel in 0..3 {
+                target[channel] = blend(target[channel], pixel[channel], alpha);
+            }
+        }
+    }
+
+    let font = style.font.as_scaled(PxScale::from(style.size));
+    let line_height = font.height() + font.line_gap();
+    let lines: Vec<&str> = text.lines().collect();
+    let text_height = line_height * lines.len() as f32;
+    let area = icon_size as f32 - TextStyle::PADDING * 2.0;
+
+    let top = TextStyle::PADDING
+        + match style.vertical_alignment {
+            VerticalAlignment::Top => 0.0,
+            VerticalAlignment::Middle => (area - text_height) / 2.0,
+            VerticalAlignment::Bottom => area - text_height,
+        };
+
+    for (line_index, line) in lines.iter().enumerate() {
+        let line_width = measure_line(&font, line);
+        let left = TextStyle::PADDING
+            + match style.horizontal_alignment {
+                HorizontalAlignment::Left => 0.0,
+                HorizontalAlignment::Center => (area - line_width) / 2.0,
+                HorizontalAlignment::Right => area - line_width,
+            };
+        let baseline = top + line_height * line_index as f32 + font.ascent();
+
+        let mut caret = left;
+        let mut previous_glyph = None;
+        for c in line.chars() {
+            let glyph_id = font.glyph_id(c);
+            if let Some(previous_glyph) = previous_glyph {
+                caret += font.kern(previous_glyph, glyph_id);
+            }
+            let glyph =
+                glyph_id.with_scale_and_position(font.scale(), ab_glyph::point(caret, baseline));
+            caret += font.h_advance(glyph_id);
+            previous_glyph = Some(glyph_id);
+
+            let Some(outline) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outline.px_bounds();
+            outline.draw(|x, y, coverage| {
+                let px = bounds.min.x as i32 + x as i32;
+                let py = bounds.min.y as i32 + y as i32;
+                if px < 0 || py < 0 || px >= icon_size as i32 || py >= icon_size as i32 {
+                    return;
+                }
+                let target = image.get_pixel_mut(px as u32, py as u32);
+                for channel in 0..3 {
+                    target[channel] = blend(target[channel], style.color[channel], coverage);
+                }
+            });
+        }
+    }
+
+    image
+}
+
+fn measure_line<F: Font, SF: ScaleFont<F>>(font: &SF, line: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous_glyph = None;
+    for c in line.chars() {
+        let glyph_id = font.glyph_id(c);
+        if let Some(previous_glyph) = previous_glyph {
+            width += font.kern(previous_glyph, glyph_id);
+        }
+        width += font.h_advance(glyph_id);
+        previous_glyph = Some(glyph_id);
+    }
+    width
+}
+
+fn blend(background: u8, foreground: u8, alpha: f32) -> u8 {
+    let alpha = alpha.clamp(0.0, 1.0);
+    (f32::from(background) * (1.0 - alpha) + f32::from(foreground) * alpha).round() as u8
+}
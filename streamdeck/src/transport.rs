@@ -0,0 +1,315 @@
+use hidapi::*;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::sync::{Arc, Mutex};
+
+use crate::{StreamDeckError, StreamDeckModel};
+
+/// The raw report I/O a `StreamDeckController` needs from a device.
+pub trait Transport: Send {
+    fn is_connected(&self) -> bool;
+
+    /// Tries to reopen a lost device.  Returns false if it is still missing.
+    fn reconnect(&mut self) -> Result<bool, StreamDeckError>;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), StreamDeckError>;
+
+    /// Returns the number of bytes read, or 0 if the timeout expired first.
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize, StreamDeckError>;
+
+    fn send_feature_report(&mut self, data: &[u8]) -> Result<(), StreamDeckError>;
+
+    /// `buf[0]` holds the report id to fetch.  Returns the number of bytes read.
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> Result<usize, StreamDeckError>;
+}
+
+/// An attached deck, as found by `StreamDeckController::list_devices`.
+#[derive(Debug, Clone)]
+pub struct StreamDeckInfo {
+    pub model: StreamDeckModel,
+    pub serial_number: Option<String>,
+    pub path: String,
+}
+
+pub struct HidTransport {
+    hid_api: HidApi,
+    hid_device: Option<HidDevice>,
+    product_id: u16,
+    serial_number: Option<String>,
+}
+
+impl HidTransport {
+    /// Lists every attached Stream Deck of a supported model.
+    pub fn enumerate() -> Result<Vec<StreamDeckInfo>, StreamDeckError> {
+        let mut hid_api = HidApi::new()?;
+
+        hid_api.refresh_devices()?;
+        Ok(hid_api
+            .device_list()
+            .filter(|dev| dev.vendor_id() == StreamDeckModel::VENDOR_ID)
+            .filter_map(|dev| {
+                StreamDeckModel::from_product_id(dev.product_id()).map(|model| StreamDeckInfo {
+                    model,
+                    serial_number: dev.serial_number().map(|s| s.to_string()),
+                    path: dev.path().to_string_lossy().to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Opens the first attached Stream Deck of a supported model.
+    pub fn open_first() -> Result<HidTransport, StreamDeckError> {
+        Self::open_matching(|_| true)?
+            .ok_or_else(|| StreamDeckError::NotFound("no supported model attached".to_string()))
+    }
+
+    pub fn open_serial(serial_number: &str) -> Result<HidTransport, StreamDeckError> {
+        Self::open_matching(|dev| dev.serial_number() == Some(serial_number))?
+            .ok_or_else(|| StreamDeckError::NotFound(format!("serial number {}", serial_number)))
+    }
+
+    fn open_matching(
+        predicate: impl Fn(&DeviceInfo) -> bool,
+    ) -> Result<Option<HidTransport>, StreamDeckError> {
+        let mut hid_api = HidApi::new()?;
+
+        hid_api.refresh_devices()?;
+        let Some(device_info) = hid_api.device_list().find(|dev| {
+            dev.vendor_id() == StreamDeckModel::VENDOR_ID
+                && StreamDeckModel::from_product_id(dev.product_id()).is_some()
+                && predicate(dev)
+        }) else {
+            return Ok(None);
+        };
+
+        let hid_device = device_info.open_device(&hid_api)?;
+        let product_id = device_info.product_id();
+        let serial_number = device_info.serial_number().map(|s| s.to_string());
+
+        Ok(Some(HidTransport {
+            hid_api,
+            hid_device: Some(hid_device),
+            product_id,
+            serial_number,
+        }))
+    }
+
+    pub fn open_path(device_path: &str) -> Result<HidTransport, StreamDeckError> {
+        let hid_api = HidApi::new()?;
+
+        let path_cstring: CString = CString::new(device_path).map_err(|_| {
+            StreamDeckError::NotFound(format!("invalid device path: {}", device_path))
+        })?;
+        let path_cstr: &CStr = path_cstring.as_c_str();
+        let hid_device = hid_api.open_path(path_cstr)?;
+
+        let device_info = hid_device.get_device_info()?;
+        let product_id = device_info.product_id();
+        let serial_number = device_info.serial_number().map(|s| s.to_string());
+
+        Ok(HidTransport {
+            hid_api,
+            hid_device: Some(hid_device),
+            product_id,
+            serial_number,
+        })
+    }
+
+    pub fn product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    // Runs a call against the open device, dropping the handle if it turns out
+    // the deck has gone away so the next read can start reconnecting.
+    fn with_device<T>(
+        &mut self,
+        f: impl FnOnce(&HidDevice) -> HidResult<T>,
+    ) -> Result<T, StreamDeckError> {
+        let result = match self.hid_device.as_ref() {
+            Some(device) => f(device).map_err(StreamDeckError::from),
+            None => Err(StreamDeckError::Disconnected),
+        };
+
+        if let Err(StreamDeckError::Disconnected) = result {
+            self.hid_device = None;
+        }
+        result
+    }
+}
+
+impl Transport for HidTransport {
+    fn is_connected(&self) -> bool {
+        self.hid_device.is_some()
+    }
+
+    fn reconnect(&mut self) -> Result<bool, StreamDeckError> {
+        self.hid_api.refresh_devices()?;
+
+        let device_info = self.hid_api.device_list().find(|dev| {
+            dev.vendor_id() == StreamDeckModel::VENDOR_ID
+                && dev.product_id() == self.product_id
+                && (self.serial_number.is_none()
+                    || dev.serial_number() == self.serial_number.as_deref())
+        });
+        match device_info {
+            Some(device_info) => {
+                self.hid_device = Some(device_info.open_device(&self.hid_api)?);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), StreamDeckError> {
+        self.with_device(|hid_device| hid_device.write(data))?;
+        Ok(())
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize, StreamDeckError> {
+        self.with_device(|hid_device| hid_device.read_timeout(buf, timeout_ms))
+    }
+
+    fn send_feature_report(&mut self, data: &[u8]) -> Result<(), StreamDeckError> {
+        self.with_device(|hid_device| hid_device.send_feature_report(data))
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> Result<usize, StreamDeckError> {
+        self.with_device(|hid_device| hid_device.get_feature_report(buf))
+    }
+}
+
+#[derive(Default)]
+struct MockTransportState {
+    plugged_in: bool,
+    is_open: bool,
+    writes: Vec<Vec<u8>>,
+    feature_reports: Vec<Vec<u8>>,
+    input_reports: VecDeque<Vec<u8>>,
+    feature_report_responses: HashMap<u8, Vec<u8>>,
+}
+
+/// In-memory transport that records everything sent to it and plays back
+/// queued input reports.  Clones share the same state, so a test can keep one
+/// clone to inspect after handing the other to a controller.
+#[derive(Clone)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockTransportState>>,
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        MockTransport {
+            state: Arc::new(Mutex::new(MockTransportState {
+                plugged_in: true,
+                is_open: true,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Queues a raw input report (key states included) for the next read.
+    pub fn push_input_report(&self, report: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .input_reports
+            .push_back(report.to_vec());
+    }
+
+    /// Sets what `get_feature_report` returns for a report id.  The response
+    /// includes the report id byte.  Unknown ids read back as zeros.
+    pub fn set_feature_report_response(&self, report_id: u8, response: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .feature_report_responses
+            .insert(report_id, response.to_vec());
+    }
+
+    /// Every output report written so far, in order.
+    pub fn written_pages(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().writes.clone()
+    }
+
+    /// Every feature report sent so far, in order.
+    pub fn feature_reports(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().feature_reports.clone()
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.writes.clear();
+        state.feature_reports.clear();
+    }
+
+    /// Simulates unplugging (false) or plugging back in (true) the device.
+    pub fn set_plugged_in(&self, plugged_in: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.plugged_in = plugged_in;
+        if !plugged_in {
+            state.is_open = false;
+        }
+    }
+
+    fn with_open_state<T>(
+        &self,
+        f: impl FnOnce(&mut MockTransportState) -> T,
+    ) -> Result<T, StreamDeckError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.is_open {
+            return Err(StreamDeckError::Disconnected);
+        }
+        Ok(f(&mut state))
+    }
+}
+
+impl Transport for MockTransport {
+    fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().is_open
+    }
+
+    fn reconnect(&mut self) -> Result<bool, StreamDeckError> {
+        let mut state = self.state.lock().unwrap();
+        state.is_open = state.plugged_in;
+        Ok(state.is_open)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), StreamDeckError> {
+        self.with_open_state(|state| state.writes.push(data.to_vec()))
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], _timeout_ms: i32) -> Result<usize, StreamDeckError> {
+        self.with_open_state(|state| match state.input_reports.pop_front() {
+            Some(report) => {
+                let len = std::cmp::min(buf.len(), report.len());
+                buf[0..len].copy_from_slice(&report[0..len]);
+                len
+            }
+            None => 0,
+        })
+    }
+
+    fn send_feature_report(&mut self, data: &[u8]) -> Result<(), StreamDeckError> {
+        self.with_open_state(|state| state.feature_reports.push(data.to_vec()))
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> Result<usize, StreamDeckError> {
+        self.with_open_state(|state| {
+            let report_id = buf[0];
+            buf.fill(0);
+            buf[0] = report_id;
+            if let Some(response) = state.feature_report_responses.get(&report_id) {
+                let len = std::cmp::min(buf.len(), response.len());
+                buf[0..len].copy_from_slice(&response[0..len]);
+            }
+            buf.len()
+        })
+    }
+}
@@ -0,0 +1,440 @@
+use log::{info, warn};
+
+use image::{DynamicImage, GenericImage, ImageOutputFormat};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::orientation::unorient_from_device;
+use crate::{ImageFormat, ModelDescriptor, Protocol, StreamDeckError, StreamDeckModel};
+use crate::{StreamDeckController, Transport};
+
+struct VirtualDeckState {
+    descriptor: &'static ModelDescriptor,
+    // Image data received so far for each key, until its last page arrives.
+    pending_key_images: HashMap<u8, Vec<u8>>,
+    pending_touch_strip_image: Vec<u8>,
+    // PNG encoded, ready to serve.
+    key_images: Vec<Option<Vec<u8>>>,
+    touch_strip: Option<DynamicImage>,
+    key_states: Vec<bool>,
+    input_reports: VecDeque<Vec<u8>>,
+    brightness: u8,
+    // Bumped on every change so the page knows when to redraw.
+    version: u64,
+}
+
+/// A pretend deck for developing without hardware.
+///
+/// It decodes the image reports a `StreamDeckController` writes and serves the
+/// result as a web page on a local address.  Pressing a key on the page
+/// sends a key press back to the controller, so an app can run unchanged.
+#[derive(Clone)]
+pub struct VirtualTransport {
+    shared: Arc<(Mutex<VirtualDeckState>, Condvar)>,
+}
+
+impl VirtualTransport {
+    const SERIAL_NUMBER: &'static str = "VIRTUAL";
+    const FIRMWARE_VERSION: &'static str = "virtual";
+
+    /// Starts serving a deck of the given model at `address`,
+    /// e.g. "127.0.0.1:8080".
+    pub fn start(
+        model: StreamDeckModel,
+        address: &str,
+    ) -> Result<VirtualTransport, StreamDeckError> {
+        let descriptor = model.descriptor();
+        let num_keys = usize::try_from(descriptor.key_count).unwrap();
+        let transport = VirtualTransport {
+            shared: Arc::new((
+                Mutex::new(VirtualDeckState {
+                    descriptor,
+                    pending_key_images: HashMap::new(),
+                    pending_touch_strip_image: vec![],
+                    key_images: vec![None; num_keys],
+                    touch_strip: None,
+                    key_states: vec![false; num_keys],
+                    input_reports: VecDeque::new(),
+                    brightness: 100,
+                    version: 0,
+                }),
+                Condvar::new(),
+            )),
+        };
+
+        let listener = TcpListener::bind(address)?;
+        info!(
+            "Virtual {} running at http://{}/",
+            descriptor.name,
+            listener.local_addr()?
+        );
+        let server = transport.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = server.handle_request(stream) {
+                    warn!("virtual stream deck request failed: {}", e);
+                }
+            }
+        });
+
+        Ok(transport)
+    }
+
+    /// Starts a virtual deck and wraps it in a controller.
+    pub fn start_controller(
+        model: StreamDeckModel,
+        address: &str,
+    ) -> Result<StreamDeckController, StreamDeckError> {
+        let transport = Self::start(model, address)?;
+        Ok(StreamDeckController::from_transport(
+            Box::new(transport),
+            model,
+        ))
+    }
+
+    /// Presses (true) or releases (false) a key, by device key index.
+    pub fn set_key_pressed(&self, device_key_index: usize, is_pressed: bool) {
+        let (state, condvar) = &*self.shared;
+        let mut state = state.lock().unwrap();
+        if device_key_index >= state.key_states.len() {
+            return;
+        }
+        state.key_states[device_key_index] = is_pressed;
+
+        let key_state_offset = state.descriptor.reports.key_state_offset;
+        let mut report = vec![0; key_state_offset + state.key_states.len()];
+        report[0] = 0x01;
+        for (report_byte, is_pressed) in report[key_state_offset..]
+            .iter_mut()
+            .zip(state.key_states.iter())
+        {
+            *report_byte = u8::from(*is_pressed);
+        }
+        state.input_reports.push_back(report);
+        condvar.notify_all();
+    }
+
+    fn handle_request(&self, stream: TcpStream) -> Result<(), StreamDeckError> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // skip the headers, nothing here needs them
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let path = parts.next().unwrap_or_default();
+        let path = path.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match (method, segments.as_slice()) {
+            ("GET", [""]) => respond(stream, "200 OK", "text/html", self.render_page().as_bytes()),
+            ("GET", ["version"]) => {
+                let (state, _) = &*self.shared;
+                let status = {
+                    let state = state.lock().unwrap();
+                    format!("{} {}", state.version, state.brightness)
+                };
+                respond(stream, "200 OK", "text/plain", status.as_bytes())
+            }
+            ("GET", ["key", key_file]) => {
+                let key_image = key_file
+                    .strip_suffix(".png")
+                    .and_then(|key| key.parse::<usize>().ok())
+                    .and_then(|device_key_index| {
+                        let (state, _) = &*self.shared;
+                        let state = state.lock().unwrap();
+                        state.key_images.get(device_key_index).cloned().flatten()
+                    });
+                match key_image {
+                    Some(key_image) => respond(stream, "200 OK", "image/png", &key_image),
+                    None => respond(stream, "404 Not Found", "text/plain", b"no image"),
+                }
+            }
+            ("GET", ["strip.png"]) => {
+                let (state, _) = &*self.shared;
+                let touch_strip = state.lock().unwrap().touch_strip.clone();
+                match touch_strip {
+                    Some(touch_strip) => {
+                        respond(stream, "200 OK", "image/png", &encode_png(&touch_strip)?)
+                    }
+                    None => respond(stream, "404 Not Found", "text/plain", b"no touch strip"),
+                }
+            }
+            ("POST", ["key", key, action @ ("down" | "up")]) => {
+                if let Ok(device_key_index) = key.parse::<usize>() {
+                    self.set_key_pressed(device_key_index, *action == "down");
+                }
+                respond(stream, "204 No Content", "text/plain", b"")
+            }
+            _ => respond(stream, "404 Not Found", "text/plain", b"not found"),
+        }
+    }
+
+    fn render_page(&self) -> String {
+        let (state, _) = &*self.shared;
+        let descriptor = state.lock().unwrap().descriptor;
+        let icon_size = descriptor.icon_size;
+
+        // lay the keys out as they sit on the device
+        let mut keys = String::new();
+        for position in 0..descriptor.key_count {
+            let column = position % descriptor.columns;
+            let device_key_index = if descriptor.reversed_key_columns {
+                position - column + (descriptor.columns - 1 - column)
+            } else {
+                position
+            };
+            keys.push_str(&format!(
+                r#"<img class="key" data-key="{device_key_index}" width="{icon_size}" height="{icon_size}">"#
+            ));
+        }
+        let touch_strip = match descriptor.touch_strip_size {
+            Some((width, height)) => format!(
+                r#"<img id="strip" width="{width}" height="{height}" style="grid-column: 1 / -1">"#
+            ),
+            None => String::new(),
+        };
+
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<title>{name}</title>
+<style>
+body {{ background: #222; color: #ccc; font-family: sans-serif; }}
+#deck {{ display: inline-grid; grid-template-columns: repeat({columns}, {icon_size}px); gap: 12px; padding: 16px; background: #000; border-radius: 16px; }}
+.key {{ background: #111; border-radius: 8px; cursor: pointer; user-select: none; }}
+.key:active {{ transform: scale(0.95); }}
+</style>
+</head>
+<body>
+<h3>{name}</h3>
+<div id="deck">{keys}{touch_strip}</div>
+<script>
+let version = null;
+for (const key of document.querySelectorAll(".key")) {{
+  const send = (action) => fetch(`/key/${{key.dataset.key}}/${{action}}`, {{ method: "POST" }});
+  key.addEventListener("mousedown", () => {{ key.pressed = true; send("down"); }});
+  for (const event of ["mouseup", "mouseleave"]) {{
+    key.addEventListener(event, () => {{ if (key.pressed) {{ key.pressed = false; send("up"); }} }});
+  }}
+  key.addEventListener("dragstart", (e) => e.preventDefault());
+}}
+async function refresh() {{
+  try {{
+    const [latest, brightness] = (await (await fetch("/version")).text()).split(" ");
+    if (latest !== version) {{
+      version = latest;
+      for (const key of document.querySelectorAll(".key")) {{
+        key.src = `/key/${{key.dataset.key}}.png?v=${{version}}`;
+      }}
+      const strip = document.getElementById("strip");
+      if (strip) strip.src = `/strip.png?v=${{version}}`;
+    }}
+    document.getElementById("deck").style.filter = `brightness(${{Math.max(brightness, 5)}}%)`;
+  }} catch (e) {{}}
+  setTimeout(refresh, 250);
+}}
+refresh();
+</script>
+</body>
+</html>
+"#,
+            name = descriptor.name,
+            columns = descriptor.columns,
+        )
+    }
+
+    fn receive_page(&self, data: &[u8]) -> Result<(), StreamDeckError> {
+        // every page header is at least this long
+        if data.len() < 16 || data[0] != 0x02 {
+            return Ok(());
+        }
+
+        let (state, _) = &*self.shared;
+        let mut state = state.lock().unwrap();
+        let read_u16 =
+            |offset: usize| usize::from(u16::from_le_bytes([data[offset], data[offset + 1]]));
+
+        match (state.descriptor.reports.protocol, data[1]) {
+            (Protocol::Gen1, 0x01) => {
+                // Gen1 headers have no length, so take a full page; a short last
+                // page just carries padding the image decoder ignores
+                let reports = &state.descriptor.reports;
+                let header_length = reports.image_report_header_length;
+                let page_end = data
+                    .len()
+                    .min(header_length + reports.image_page_payload_length);
+                let payload = data.get(header_length..page_end).unwrap_or_default();
+                let is_last_page = data[4] != 0;
+                state.receive_key_page(data[5].wrapping_sub(1), payload, is_last_page)
+            }
+            (Protocol::Gen2, 0x07) => {
+                let header_length = state.descriptor.reports.image_report_header_length;
+                let payload = data
+                    .get(header_length..header_length + read_u16(4))
+                    .unwrap_or_default();
+                state.receive_key_page(data[2], payload, data[3] != 0)
+            }
+            (Protocol::Gen2, 0x0c) => {
+                let payload = data.get(16..16 + read_u16(13)).unwrap_or_default();
+                state.pending_touch_strip_image.extend_from_slice(payload);
+                if data[10] != 0 {
+                    let image_buf = std::mem::take(&mut state.pending_touch_strip_image);
+                    let image = image::load_from_memory(&image_buf)?;
+                    if let Some((width, height)) = state.descriptor.touch_strip_size {
+                        let touch_strip = state
+                            .touch_strip
+                            .get_or_insert_with(|| DynamicImage::new_rgb8(width, height));
+                        let (x, y) = (read_u16(2), read_u16(4));
+                        // ignore areas that don't fit rather than panic
+                        let _ = touch_strip.copy_from(&image, x as u32, y as u32);
+                    }
+                    state.version += 1;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl VirtualDeckState {
+    fn receive_key_page(
+        &mut self,
+        device_key_index: u8,
+        payload: &[u8],
+        is_last_page: bool,
+    ) -> Result<(), StreamDeckError> {
+        self.pending_key_images
+            .entry(device_key_index)
+            .or_default()
+            .extend_from_slice(payload);
+        if !is_last_page {
+            return Ok(());
+        }
+
+        let image_buf = self
+            .pending_key_images
+            .remove(&device_key_index)
+            .unwrap_or_default();
+        let format = match self.descriptor.image_format {
+            ImageFormat::Bmp => image::ImageFormat::Bmp,
+            ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+        };
+        let image = unorient_from_device(
+            self.descriptor,
+            image::load_from_memory_with_format(&image_buf, format)?,
+        );
+
+        if let Some(key_image) = self.key_images.get_mut(usize::from(device_key_index)) {
+            *key_image = Some(encode_png(&image)?);
+            self.version += 1;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.key_images.fill(None);
+        self.touch_strip = None;
+        self.version += 1;
+    }
+}
+
+impl Transport for VirtualTransport {
+    fn is_connected(&self) -> bool {
+        true
+    }
+
+    fn reconnect(&mut self) -> Result<bool, StreamDeckError> {
+        Ok(true)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), StreamDeckError> {
+        self.receive_page(data)
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize, StreamDeckError> {
+        let (state, condvar) = &*self.shared;
+        let timeout = Duration::from_millis(u64::try_from(timeout_ms).unwrap_or(0));
+        let (mut state, _) = condvar
+            .wait_timeout_while(state.lock().unwrap(), timeout, |state| {
+                state.input_reports.is_empty()
+            })
+            .unwrap();
+
+        match state.input_reports.pop_front() {
+            Some(report) => {
+                let len = std::cmp::min(buf.len(), report.len());
+                buf[0..len].copy_from_slice(&report[0..len]);
+                Ok(len)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn send_feature_report(&mut self, data: &[u8]) -> Result<(), StreamDeckError> {
+        let (state, _) = &*self.shared;
+        let mut state = state.lock().unwrap();
+        match (state.descriptor.reports.protocol, data) {
+            (Protocol::Gen1, [0x05, 0x55, 0xaa, 0xd1, 0x01, brightness, ..])
+            | (Protocol::Gen2, [0x03, 0x08, brightness, ..]) => {
+                state.brightness = *brightness;
+                state.version += 1;
+            }
+            (Protocol::Gen1, [0x0b, 0x63, ..]) | (Protocol::Gen2, [0x03, 0x02, ..]) => {
+                state.clear()
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> Result<usize, StreamDeckError> {
+        let (state, _) = &*self.shared;
+        let protocol = state.lock().unwrap().descriptor.reports.protocol;
+
+        let (value, offset) = match (protocol, buf[0]) {
+            (Protocol::Gen1, 0x03) => (Self::SERIAL_NUMBER, 5),
+            (Protocol::Gen1, 0x04) => (Self::FIRMWARE_VERSION, 5),
+            (Protocol::Gen2, 0x05) => (Self::FIRMWARE_VERSION, 6),
+            (Protocol::Gen2, 0x06) => (Self::SERIAL_NUMBER, 2),
+            _ => ("", buf.len()),
+        };
+        buf[1..].fill(0);
+        let len = std::cmp::min(value.len(), buf.len().saturating_sub(offset));
+        buf[offset..offset + len].copy_from_slice(&value.as_bytes()[0..len]);
+        Ok(buf.len())
+    }
+}
+
+fn encode_png(image: &DynamicImage) -> Result<Vec<u8>, StreamDeckError> {
+    let mut png_buf: Vec<u8> = vec![];
+    image.write_to(&mut Cursor::new(&mut png_buf), ImageOutputFormat::Png)?;
+    Ok(png_buf)
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), StreamDeckError> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    Ok(())
+}
@@ -1,49 +1,73 @@
 use anyhow::Result;
 
-use quick_xml::de::from_str;
-use serde::Deserialize;
-
-pub struct DenonReceiver {
-    device_address: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct DenonSummaryValueItem {
-    #[serde(rename = "$value")]
-    text: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct DenonSummaryItem {
-    #[serde(rename = "value")]
-    value: DenonSummaryValueItem,
+use log::warn;
+
+use crate::denon_listener::DenonStatusListener;
+use crate::denon_status::{DenonZoneSummary, ReceiverStatus};
+use crate::denon_telnet::DenonTelnet;
+use crate::denon_zone::{DenonZone, Zone};
+use crate::volume::Volume;
+use std::sync::Arc;
+
+/// How commands reach the receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenonProtocol {
+    /// The web UI's `formiPhoneAppDirect.xml` endpoint.  Fire and forget.
+    Http,
+    /// The control protocol on TCP port 23, which acknowledges each command.
+    Telnet,
 }
 
-#[derive(Debug, Deserialize)]
-struct DenonSummaryRaw {
-    #[serde(rename = "Mute")]
-    mute: DenonSummaryItem,
-
-    #[serde(rename = "VolumeDisplay")]
-    volume_display: DenonSummaryItem,
-
-    #[serde(rename = "MasterVolume")]
-    master_volume: DenonSummaryItem,
+enum DenonConnection {
+    Http,
+    Telnet(Arc<DenonTelnet>),
 }
 
-struct DenonSummary {
-    #[allow(dead_code)]
-    is_muted: bool,
-    volume: i32,
+pub struct DenonReceiver {
+    device_address: String,
+    connection: DenonConnection,
+    max_volume: Volume,
 }
 
 impl DenonReceiver {
+    // +15dB, well short of the receiver's own 98.
+    const DEFAULT_MAX_VOLUME: f32 = 95.0;
+
     pub fn new(device_address: &str) -> DenonReceiver {
+        DenonReceiver::with_protocol(device_address, DenonProtocol::Http)
+    }
+
+    pub fn with_protocol(device_address: &str, protocol: DenonProtocol) -> DenonReceiver {
+        let connection = match protocol {
+            DenonProtocol::Http => DenonConnection::Http,
+            DenonProtocol::Telnet => {
+                DenonConnection::Telnet(Arc::new(DenonTelnet::new(device_address)))
+            }
+        };
         DenonReceiver {
             device_address: device_address.to_string(),
+            connection,
+            max_volume: Volume::from_absolute(Self::DEFAULT_MAX_VOLUME),
         }
     }
 
+    pub fn get_protocol(&self) -> DenonProtocol {
+        match self.connection {
+            DenonConnection::Http => DenonProtocol::Http,
+            DenonConnection::Telnet(_) => DenonProtocol::Telnet,
+        }
+    }
+
+    /// Starts following the receiver's status pushes.  Shares the command
+    /// connection when using telnet, since the receiver only allows one.
+    pub fn start_status_listener(&self) -> DenonStatusListener {
+        let telnet = match &self.connection {
+            DenonConnection::Http => Arc::new(DenonTelnet::new(&self.device_address)),
+            DenonConnection::Telnet(telnet) => telnet.clone(),
+        };
+        DenonStatusListener::start(telnet)
+    }
+
     pub fn turn_on(&self) -> Result<()> {
         // TODO: determine which (if not possibly both) that I need.
 
@@ -61,36 +85,70 @@ impl DenonReceiver {
         self.send_command("PWSTANDBY")?;
 
         // main zone power
-        self.send_command("ZMSTANDBY")?;
+        self.send_command("ZMOFF")?;
 
         Ok(())
     }
 
-    pub fn set_volume(&self, volume: i32) -> Result<()> {
-        self.send_command(&format!("MV{:2}", num::clamp(volume, 0, 95)))?;
-        Ok(())
+    /// Capped at `get_max_volume`.
+    pub fn set_volume(&self, volume: Volume) -> Result<()> {
+        self.zone(Zone::Main).set_volume(volume)
+    }
+
+    /// None while the receiver has no volume to report, e.g. in standby.
+    pub fn get_volume(&self) -> Result<Option<Volume>> {
+        self.zone(Zone::Main).get_volume()
     }
 
-    pub fn get_volume(&self) -> Result<i32> {
-        Ok(self.load_summary()?.volume)
+    pub fn volume_up(&self) -> Result<()> {
+        self.zone(Zone::Main).volume_up()
+    }
+
+    pub fn volume_down(&self) -> Result<()> {
+        self.zone(Zone::Main).volume_down()
+    }
+
+    /// The loudest any zone will be set to from here, whatever is asked for.
+    pub fn set_max_volume(&mut self, max_volume: Volume) {
+        self.max_volume = max_volume;
+    }
+
+    pub fn get_max_volume(&self) -> Volume {
+        self.max_volume
     }
 
     pub fn mute(&self, mute: bool) -> Result<()> {
-        if mute {
-            self.send_command("MUOFF")?;
-        }
-        Ok(())
+        self.zone(Zone::Main).mute(mute)
     }
 
     pub fn is_muted(&self) -> Result<bool> {
-        let summary = self.load_summary()?;
-        Ok(summary.is_muted)
+        self.zone(Zone::Main).is_muted()
     }
 
-    pub fn select_input(&self, input: ReceiverInput) -> Result<()> {
-        self.send_command(&format!("SI{}", receiver_input_to_command_string(input)))?;
+    pub fn zone(&self, zone: Zone) -> DenonZone<'_> {
+        DenonZone::new(self, zone)
+    }
 
-        Ok(())
+    /// Reads the full status page, including the zones the receiver has.
+    /// Always uses HTTP, since telnet can't report source names.
+    pub fn get_status(&self) -> Result<ReceiverStatus> {
+        let main_zone = self.load_summary(Zone::Main)?;
+        // receivers without the zone answer with an error or an empty page
+        let zone2 = self.zone(Zone::Zone2).get_status().ok();
+        let zone3 = self.zone(Zone::Zone3).get_status().ok();
+
+        Ok(ReceiverStatus {
+            is_powered_on: main_zone.is_powered_on,
+            main_zone: main_zone.zone,
+            surround_mode: main_zone.surround_mode,
+            sources: main_zone.sources,
+            zone2,
+            zone3,
+        })
+    }
+
+    pub fn select_input(&self, input: ReceiverInput) -> Result<()> {
+        self.zone(Zone::Main).select_input(input)
     }
 
     fn get_cmd_url(&self) -> String {
@@ -104,44 +162,32 @@ impl DenonReceiver {
         format!("http://{}/goform/", self.device_address)
     }
 
-    fn send_command(&self, command: &str) -> Result<()> {
-        let url = format!("{}{}", self.get_cmd_url(), urlencoding::encode(command));
-        reqwest::blocking::get(url)?;
-        Ok(())
+    pub(crate) fn get_telnet(&self) -> Option<&DenonTelnet> {
+        match &self.connection {
+            DenonConnection::Http => None,
+            DenonConnection::Telnet(telnet) => Some(telnet),
+        }
     }
 
-    fn load_summary(&self) -> Result<DenonSummary> {
-        let url = format!("{}formMainZone_MainZoneXml.xml", self.get_status_url());
-        // info!("{}", url);
-        let response = reqwest::blocking::get(url).unwrap();
-        let response_text = response.text().unwrap();
-
-        // info!("{}", response_text);
-
-        let summary_struct: DenonSummaryRaw = from_str(&response_text)?;
-
-        let is_muted = summary_struct.mute.value.text == "on";
-        let is_volume_absolute = summary_struct.volume_display.value.text == "Absolute";
-        let volume_value = summary_struct.master_volume.value.text;
-        let mut volume = 0;
-        if volume_value != "--" {
-            let f = volume_value.parse::<f64>()?;
-
-            let mut v = if f > f64::from(i32::MAX) {
-                i32::MAX
-            } else if f < f64::from(i32::MIN) {
-                i32::MIN
-            } else {
-                f.round() as i32
-            };
-
-            if is_volume_absolute {
-                v += 80;
+    pub(crate) fn send_command(&self, command: &str) -> Result<()> {
+        match &self.connection {
+            DenonConnection::Http => {
+                let url = format!("{}{}", self.get_cmd_url(), urlencoding::encode(command));
+                reqwest::blocking::get(url)?;
+            }
+            DenonConnection::Telnet(telnet) => {
+                if telnet.send_command(command)?.is_empty() {
+                    warn!("denon receiver did not acknowledge {}", command);
+                }
             }
-            volume = v;
         }
+        Ok(())
+    }
 
-        Ok(DenonSummary { volume, is_muted })
+    pub(crate) fn load_summary(&self, zone: Zone) -> Result<DenonZoneSummary> {
+        let url = format!("{}{}", self.get_status_url(), zone.status_page());
+        let response_text = reqwest::blocking::get(url)?.error_for_status()?.text()?;
+        DenonZoneSummary::parse(&response_text)
     }
 }
 
@@ -174,7 +220,7 @@ pub enum ReceiverInput {
     UsbFavorites,
 }
 
-fn receiver_input_to_command_string(input: ReceiverInput) -> String {
+pub(crate) fn receiver_input_to_command_string(input: ReceiverInput) -> String {
     match input {
         ReceiverInput::Phono => "PHONO".to_string(),
         ReceiverInput::Cd => "CD".to_string(),
@@ -204,3 +250,88 @@ fn receiver_input_to_command_string(input: ReceiverInput) -> String {
         ReceiverInput::UsbFavorites => "FVP".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+
+    // Answers every request with an empty 200 and records the commands sent
+    // to formiPhoneAppDirect.xml.
+    fn fake_receiver() -> (DenonReceiver, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let commands = Arc::new(Mutex::new(vec![]));
+
+        let received = commands.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = vec![];
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(len @ 1..) => request.extend_from_slice(&buf[..len]),
+                        _ => break,
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                if let Some((_, command)) = path.split_once('?') {
+                    let command = urlencoding::decode(command).unwrap().into_owned();
+                    received.lock().unwrap().push(command);
+                }
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+            }
+        });
+
+        (DenonReceiver::new(&address), commands)
+    }
+
+    fn take(commands: &Mutex<Vec<String>>) -> Vec<String> {
+        std::mem::take(&mut *commands.lock().unwrap())
+    }
+
+    #[test]
+    fn sends_mute_commands() {
+        let (receiver, commands) = fake_receiver();
+        receiver.mute(true).unwrap();
+        receiver.mute(false).unwrap();
+        receiver.zone(Zone::Zone2).mute(true).unwrap();
+        assert_eq!(take(&commands), ["MUON", "MUOFF", "Z2MUON"]);
+    }
+
+    #[test]
+    fn sends_power_commands() {
+        let (receiver, commands) = fake_receiver();
+        receiver.turn_on().unwrap();
+        assert_eq!(take(&commands), ["PWON", "ZMON"]);
+        receiver.turn_off().unwrap();
+        assert_eq!(take(&commands), ["PWSTANDBY", "ZMOFF"]);
+    }
+
+    #[test]
+    fn sends_zero_padded_volumes() {
+        let (receiver, commands) = fake_receiver();
+        receiver.set_volume(Volume::from_absolute(5.0)).unwrap();
+        receiver.set_volume(Volume::from_absolute(0.5)).unwrap();
+        receiver.set_volume(Volume::from_absolute(45.5)).unwrap();
+        receiver
+            .zone(Zone::Zone3)
+            .set_volume(Volume::from_absolute(8.0))
+            .unwrap();
+        assert_eq!(take(&commands), ["MV05", "MV005", "MV455", "Z308"]);
+    }
+
+    #[test]
+    fn caps_volume_at_the_maximum() {
+        let (mut receiver, commands) = fake_receiver();
+        receiver.set_max_volume(Volume::from_absolute(70.0));
+        receiver.set_volume(Volume::from_absolute(90.0)).unwrap();
+        assert_eq!(take(&commands), ["MV70"]);
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::{anyhow, Result};
+
+use crate::denon::DenonReceiver;
+use crate::denon_telnet::{parse_volume, DenonResponse};
+use crate::denon_zone::Zone;
+
+/// The main zone's listening mode.
+///
+/// `Movie`, `Music` and `Game` pick the receiver's preferred mode for that
+/// kind of content, so the receiver reports back whichever mode it chose.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SurroundMode {
+    Movie,
+    Music,
+    Game,
+    Direct,
+    PureDirect,
+    Stereo,
+    Auto,
+    MultiChannelStereo,
+    Virtual,
+    /// Any Dolby mode, e.g. "DOLBY DIGITAL" or "DOLBY ATMOS".
+    Dolby(String),
+    /// Any DTS mode, e.g. "DTS SURROUND" or "DTS NEO:6 C".
+    Dts(String),
+    Other(String),
+}
+
+impl SurroundMode {
+    pub fn parse(value: &str) -> SurroundMode {
+        let value = value.trim().to_uppercase();
+        match value.as_str() {
+            "MOVIE" => SurroundMode::Movie,
+            "MUSIC" => SurroundMode::Music,
+            "GAME" => SurroundMode::Game,
+            "DIRECT" => SurroundMode::Direct,
+            "PURE DIRECT" => SurroundMode::PureDirect,
+            "STEREO" => SurroundMode::Stereo,
+            "AUTO" => SurroundMode::Auto,
+            "MCH STEREO" => SurroundMode::MultiChannelStereo,
+            "VIRTUAL" => SurroundMode::Virtual,
+            v if v.starts_with("DOLBY") => SurroundMode::Dolby(value),
+            v if v.starts_with("DTS") => SurroundMode::Dts(value),
+            _ => SurroundMode::Other(value),
+        }
+    }
+
+    fn to_command_string(&self) -> &str {
+        match self {
+            SurroundMode::Movie => "MOVIE",
+            SurroundMode::Music => "MUSIC",
+            SurroundMode::Game => "GAME",
+            SurroundMode::Direct => "DIRECT",
+            SurroundMode::PureDirect => "PURE DIRECT",
+            SurroundMode::Stereo => "STEREO",
+            SurroundMode::Auto => "AUTO",
+            SurroundMode::MultiChannelStereo => "MCH STEREO",
+            SurroundMode::Virtual => "VIRTUAL",
+            SurroundMode::Dolby(mode) | SurroundMode::Dts(mode) | SurroundMode::Other(mode) => mode,
+        }
+    }
+}
+
+/// Audyssey MultEQ room correction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultEq {
+    Audyssey,
+    BypassLeftRight,
+    Flat,
+    Manual,
+    Off,
+}
+
+impl MultEq {
+    const VALUES: [(MultEq, &'static str); 5] = [
+        (MultEq::Audyssey, "AUDYSSEY"),
+        (MultEq::BypassLeftRight, "BYP.LR"),
+        (MultEq::Flat, "FLAT"),
+        (MultEq::Manual, "MANUAL"),
+        (MultEq::Off, "OFF"),
+    ];
+}
+
+/// How hard the receiver boosts speech over everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialogEnhancer {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl DialogEnhancer {
+    const VALUES: [(DialogEnhancer, &'static str); 4] = [
+        (DialogEnhancer::Off, "OFF"),
+        (DialogEnhancer::Low, "LOW"),
+        (DialogEnhancer::Medium, "MED"),
+        (DialogEnhancer::High, "HIGH"),
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    FrontLeft,
+    FrontRight,
+    Center,
+    Subwoofer,
+    SurroundLeft,
+    SurroundRight,
+    SurroundBackLeft,
+    SurroundBackRight,
+    SurroundBack,
+    FrontHeightLeft,
+    FrontHeightRight,
+}
+
+impl Channel {
+    const VALUES: [(Channel, &'static str); 11] = [
+        (Channel::FrontLeft, "FL"),
+        (Channel::FrontRight, "FR"),
+        (Channel::Center, "C"),
+        (Channel::Subwoofer, "SW"),
+        (Channel::SurroundLeft, "SL"),
+        (Channel::SurroundRight, "SR"),
+        (Channel::SurroundBackLeft, "SBL"),
+        (Channel::SurroundBackRight, "SBR"),
+        (Channel::SurroundBack, "SB"),
+        (Channel::FrontHeightLeft, "FHL"),
+        (Channel::FrontHeightRight, "FHR"),
+    ];
+}
+
+fn to_command_string<T: PartialEq + Copy>(values: &[(T, &'static str)], value: T) -> &'static str {
+    values.iter().find(|(v, _)| *v == value).unwrap().1
+}
+
+fn from_command_string<T: Copy>(values: &[(T, &'static str)], value: &str) -> Option<T> {
+    values.iter().find(|(_, s)| *s == value).map(|(v, _)| *v)
+}
+
+/// One "PS" line about the main zone's audio.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioSetting {
+    ToneControl(bool),
+    /// In dB, -6 to +6.
+    Bass(f32),
+    /// In dB, -6 to +6.
+    Treble(f32),
+    DynamicEq(bool),
+    MultEq(MultEq),
+    DialogLevelAdjust(bool),
+    /// In dB, -12 to +12.
+    DialogLevel(f32),
+    DialogEnhancer(DialogEnhancer),
+    Other(String),
+}
+
+impl AudioSetting {
+    pub(crate) fn parse(value: &str) -> AudioSetting {
+        let on_off = |v: &str| match v {
+            "ON" => Some(true),
+            "OFF" => Some(false),
+            _ => None,
+        };
+
+        let parsed = if let Some(v) = value.strip_prefix("TONE CTRL ") {
+            on_off(v).map(AudioSetting::ToneControl)
+        } else if let Some(v) = value.strip_prefix("BAS ") {
+            parse_level(v).map(AudioSetting::Bass)
+        } else if let Some(v) = value.strip_prefix("TRE ") {
+            parse_level(v).map(AudioSetting::Treble)
+        } else if let Some(v) = value.strip_prefix("DYNEQ ") {
+            on_off(v).map(AudioSetting::DynamicEq)
+        } else if let Some(v) = value.strip_prefix("MULTEQ:") {
+            from_command_string(&MultEq::VALUES, v).map(AudioSetting::MultEq)
+        } else if let Some(v) = value.strip_prefix("DIL ") {
+            on_off(v)
+                .map(AudioSetting::DialogLevelAdjust)
+                .or_else(|| parse_level(v).map(AudioSetting::DialogLevel))
+        } else if let Some(v) = value.strip_prefix("DEH ") {
+            from_command_string(&DialogEnhancer::VALUES, v).map(AudioSetting::DialogEnhancer)
+        } else {
+            None
+        };
+        parsed.unwrap_or_else(|| AudioSetting::Other(value.to_string()))
+    }
+}
+
+/// Parses a "CV" line, e.g. "FL 505" is the front left channel at +0.5dB.
+pub(crate) fn parse_channel_level(value: &str) -> Option<(Channel, f32)> {
+    let (channel, level) = value.split_once(' ')?;
+    Some((
+        from_command_string(&Channel::VALUES, channel)?,
+        parse_level(level)?,
+    ))
+}
+
+/// What the main zone's tone and room correction are set to.  Fields are
+/// None when the receiver doesn't have the setting.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioSettings {
+    pub tone_control: Option<bool>,
+    pub bass: Option<f32>,
+    pub treble: Option<f32>,
+    pub dynamic_eq: Option<bool>,
+    pub mult_eq: Option<MultEq>,
+    pub dialog_level_adjust: Option<bool>,
+    pub dialog_level: Option<f32>,
+    pub dialog_enhancer: Option<DialogEnhancer>,
+}
+
+// Levels are sent as 50 + dB, with a third digit for half steps ("445" is -0.5dB).
+fn parse_level(value: &str) -> Option<f32> {
+    parse_volume(value).map(|level| level - 50.0)
+}
+
+fn format_level(db: f32, min_db: f32, max_db: f32) -> String {
+    let level = (num::clamp(db, min_db, max_db) + 50.0) * 2.0;
+    let level = level.round() as i32;
+    if level % 2 == 0 {
+        format!("{:02}", level / 2)
+    } else {
+        format!("{:02}5", level / 2)
+    }
+}
+
+impl DenonReceiver {
+    pub fn set_surround_mode(&self, mode: &SurroundMode) -> Result<()> {
+        self.send_command(&format!("MS{}", mode.to_command_string()))
+    }
+
+    pub fn get_surround_mode(&self) -> Result<SurroundMode> {
+        match self.get_telnet() {
+            None => self
+                .load_summary(Zone::Main)?
+                .surround_mode
+                .ok_or_else(|| anyhow!("receiver did not report its surround mode")),
+            Some(telnet) => telnet
+                .send_command("MS?")?
+                .into_iter()
+                .find_map(|response| match response {
+                    DenonResponse::SurroundMode(mode) => Some(mode),
+                    _ => None,
+                })
+                .ok_or_else(|| anyhow!("receiver did not report its surround mode")),
+        }
+    }
+
+    pub fn set_tone_control(&self, on: bool) -> Result<()> {
+        self.send_command(if on {
+            "PSTONE CTRL ON"
+        } else {
+            "PSTONE CTRL OFF"
+        })
+    }
+
+    /// Only takes effect while tone control is on.
+    pub fn set_bass(&self, db: f32) -> Result<()> {
+        self.send_command(&format!("PSBAS {}", format_level(db.round(), -6.0, 6.0)))
+    }
+
+    /// Only takes effect while tone control is on.
+    pub fn set_treble(&self, db: f32) -> Result<()> {
+        self.send_command(&format!("PSTRE {}", format_level(db.round(), -6.0, 6.0)))
+    }
+
+    pub fn set_dynamic_eq(&self, on: bool) -> Result<()> {
+        self.send_command(if on { "PSDYNEQ ON" } else { "PSDYNEQ OFF" })
+    }
+
+    pub fn set_mult_eq(&self, mult_eq: MultEq) -> Result<()> {
+        self.send_command(&format!(
+            "PSMULTEQ:{}",
+            to_command_string(&MultEq::VALUES, mult_eq)
+        ))
+    }
+
+    pub fn set_dialog_level_adjust(&self, on: bool) -> Result<()> {
+        self.send_command(if on { "PSDIL ON" } else { "PSDIL OFF" })
+    }
+
+    /// Only takes effect while dialog level adjust is on.
+    pub fn set_dialog_level(&self, db: f32) -> Result<()> {
+        self.send_command(&format!("PSDIL {}", format_level(db, -12.0, 12.0)))
+    }
+
+    pub fn set_dialog_enhancer(&self, level: DialogEnhancer) -> Result<()> {
+        self.send_command(&format!(
+            "PSDEH {}",
+            to_command_string(&DialogEnhancer::VALUES, level)
+        ))
+    }
+
+    pub fn set_channel_level(&self, channel: Channel, db: f32) -> Result<()> {
+        self.send_command(&format!(
+            "CV{} {}",
+            to_command_string(&Channel::VALUES, channel),
+            format_level(db, -12.0, 12.0)
+        ))
+    }
+
+    /// Needs the telnet protocol, since the status page doesn't include these.
+    pub fn get_audio_settings(&self) -> Result<AudioSettings> {
+        let telnet = self
+            .get_telnet()
+            .ok_or_else(|| anyhow!("reading audio settings needs the telnet protocol"))?;
+
+        let mut settings = AudioSettings::default();
+        for query in [
+            "PSTONE CTRL ?",
+            "PSBAS ?",
+            "PSTRE ?",
+            "PSDYNEQ ?",
+            "PSMULTEQ: ?",
+            "PSDIL ?",
+            "PSDEH ?",
+        ] {
+            for response in telnet.send_command(query)? {
+                match response {
+                    DenonResponse::Audio(AudioSetting::ToneControl(on)) => {
+                        settings.tone_control = Some(on)
+                    }
+                    DenonResponse::Audio(AudioSetting::Bass(db)) => settings.bass = Some(db),
+                    DenonResponse::Audio(AudioSetting::Treble(db)) => settings.treble = Some(db),
+                    DenonResponse::Audio(AudioSetting::DynamicEq(on)) => {
+                        settings.dynamic_eq = Some(on)
+                    }
+                    DenonResponse::Audio(AudioSetting::MultEq(mult_eq)) => {
+                        settings.mult_eq = Some(mult_eq)
+                    }
+                    DenonResponse::Audio(AudioSetting::DialogLevelAdjust(on)) => {
+                        settings.dialog_level_adjust = Some(on)
+                    }
+                    DenonResponse::Audio(AudioSetting::DialogLevel(db)) => {
+                        settings.dialog_level = Some(db)
+                    }
+                    DenonResponse::Audio(AudioSetting::DialogEnhancer(level)) => {
+                        settings.dialog_enhancer = Some(level)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    /// The level of each channel the speaker setup has, in dB.  Needs the
+    /// telnet protocol.
+    pub fn get_channel_levels(&self) -> Result<Vec<(Channel, f32)>> {
+        let telnet = self
+            .get_telnet()
+            .ok_or_else(|| anyhow!("reading channel levels needs the telnet protocol"))?;
+
+        Ok(telnet
+            .send_command("CV?")?
+            .into_iter()
+            .filter_map(|response| match response {
+                DenonResponse::ChannelLevel(channel, db) => Some((channel, db)),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_levels() {
+        let cases = [
+            (0.0, "50"),
+            (-0.5, "495"),
+            (0.5, "505"),
+            (-6.0, "44"),
+            (6.0, "56"),
+            (-10.0, "40"),
+            (-12.0, "38"),
+            (12.0, "62"),
+            (1.3, "515"),
+        ];
+        for (db, expected) in cases {
+            assert_eq!(format_level(db, -12.0, 12.0), expected, "{}", db);
+        }
+    }
+
+    #[test]
+    fn clamps_levels() {
+        assert_eq!(format_level(-20.0, -12.0, 12.0), "38");
+        assert_eq!(format_level(20.0, -12.0, 12.0), "62");
+        assert_eq!(format_level(9.0, -6.0, 6.0), "56");
+    }
+
+    #[test]
+    fn parses_levels() {
+        assert_eq!(parse_level("50"), Some(0.0));
+        assert_eq!(parse_level("495"), Some(-0.5));
+        assert_eq!(parse_level("62"), Some(12.0));
+        assert_eq!(parse_level("ON"), None);
+        for level in ["38", "445", "50", "505", "62"] {
+            let db = parse_level(level).unwrap();
+            assert_eq!(format_level(db, -12.0, 12.0), level);
+        }
+    }
+}
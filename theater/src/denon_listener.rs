@@ -0,0 +1,302 @@
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time;
+
+use crate::denon_audio::SurroundMode;
+use crate::denon_telnet::{DenonResponse, DenonTelnet, ZoneResponse};
+use crate::denon_zone::Zone;
+use crate::volume::Volume;
+
+/// What the receiver last reported about itself.  Fields are None until the
+/// receiver has said.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReceiverState {
+    pub is_powered_on: Option<bool>,
+    pub is_main_zone_on: Option<bool>,
+    pub volume: Option<Volume>,
+    /// The most the receiver itself allows.
+    pub max_volume: Option<Volume>,
+    pub is_muted: Option<bool>,
+    /// The source as the receiver names it, e.g. "GAME" or "SAT/CBL".
+    pub input: Option<String>,
+    pub surround_mode: Option<SurroundMode>,
+    pub zone2: ZoneState,
+    pub zone3: ZoneState,
+}
+
+/// What zone 2 or 3 last reported.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ZoneState {
+    pub is_on: Option<bool>,
+    pub volume: Option<Volume>,
+    pub is_muted: Option<bool>,
+    pub input: Option<String>,
+}
+
+fn update<T: PartialEq>(field: &mut Option<T>, value: T) -> bool {
+    let is_changed = field.as_ref() != Some(&value);
+    *field = Some(value);
+    is_changed
+}
+
+impl ZoneState {
+    fn apply(&mut self, response: ZoneResponse) -> bool {
+        match response {
+            ZoneResponse::Power(on) => update(&mut self.is_on, on),
+            ZoneResponse::Volume(volume) => update(&mut self.volume, volume),
+            ZoneResponse::Mute(is_muted) => update(&mut self.is_muted, is_muted),
+            ZoneResponse::Input(input) => update(&mut self.input, input),
+            ZoneResponse::Other(_) => false,
+        }
+    }
+}
+
+impl ReceiverState {
+    /// Returns whether anything changed.
+    fn apply(&mut self, response: DenonResponse) -> bool {
+        match response {
+            DenonResponse::Power(on) => update(&mut self.is_powered_on, on),
+            DenonResponse::MainZonePower(on) => update(&mut self.is_main_zone_on, on),
+            DenonResponse::Volume(volume) => update(&mut self.volume, volume),
+            DenonResponse::MaxVolume(volume) => update(&mut self.max_volume, volume),
+            DenonResponse::Mute(is_muted) => update(&mut self.is_muted, is_muted),
+            DenonResponse::Input(input) => update(&mut self.input, input),
+            DenonResponse::SurroundMode(mode) => update(&mut self.surround_mode, mode),
+            DenonResponse::Zone(Zone::Zone2, response) => self.zone2.apply(response),
+            DenonResponse::Zone(Zone::Zone3, response) => self.zone3.apply(response),
+            DenonResponse::Audio(_)
+            | DenonResponse::ChannelLevel(_, _)
+            | DenonResponse::Zone(Zone::Main, _)
+            | DenonResponse::Other(_) => false,
+        }
+    }
+}
+
+/// Keeps a `ReceiverState` up to date from the status lines the receiver
+/// pushes over its telnet port, including changes made with its own remote.
+///
+/// Runs until dropped, reconnecting whenever the receiver goes away.
+pub struct DenonStatusListener {
+    state: Arc<Mutex<ReceiverState>>,
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<ReceiverState>>>>,
+    is_running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DenonStatusListener {
+    const RECONNECT_DELAY_MS: u64 = 5000;
+    // How long the thread can go without checking whether it should stop.
+    const POLL_MS: u64 = 250;
+    // Receivers without zone 3 just don't answer its queries.
+    const STATUS_QUERIES: [&'static str; 10] = [
+        "PW?", "ZM?", "MV?", "MU?", "SI?", "MS?", "Z2?", "Z2MU?", "Z3?", "Z3MU?",
+    ];
+
+    pub fn start(telnet: Arc<DenonTelnet>) -> DenonStatusListener {
+        let state = Arc::new(Mutex::new(ReceiverState::default()));
+        let subscribers = Arc::new(Mutex::new(vec![]));
+        let is_running = Arc::new(AtomicBool::new(true));
+
+        let thread_state = state.clone();
+        let thread_subscribers = subscribers.clone();
+        let thread_is_running = is_running.clone();
+        let thread = thread::spawn(move || {
+            run_listener(
+                &telnet,
+                &thread_state,
+                &thread_subscribers,
+                &thread_is_running,
+            )
+        });
+
+        DenonStatusListener {
+            state,
+            subscribers,
+            is_running,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn get_state(&self) -> ReceiverState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Receives the whole state each time part of it changes.
+    pub fn subscribe(&self) -> mpsc::Receiver<ReceiverState> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+impl Drop for DenonStatusListener {
+    fn drop(&mut self) {
+        self.is_running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_listener(
+    telnet: &DenonTelnet,
+    state: &Mutex<ReceiverState>,
+    subscribers: &Mutex<Vec<mpsc::Sender<ReceiverState>>>,
+    is_running: &AtomicBool,
+) {
+    let poll = time::Duration::from_millis(DenonStatusListener::POLL_MS);
+    let mut is_connected = true;
+
+    while is_running.load(Ordering::SeqCst) {
+        let responses = match telnet.subscribe() {
+            Ok(responses) => responses,
+            Err(e) => {
+                if is_connected {
+                    warn!("lost the denon status connection, retrying: {}", e);
+                    is_connected = false;
+                }
+                let mut waited = 0;
+                while waited < DenonStatusListener::RECONNECT_DELAY_MS
+                    && is_running.load(Ordering::SeqCst)
+                {
+                    thread::sleep(poll);
+                    waited += DenonStatusListener::POLL_MS;
+                }
+                continue;
+            }
+        };
+        if !is_connected {
+            info!("denon status connection restored");
+            is_connected = true;
+        }
+
+        // the answers arrive through `responses` like any other status line
+        for query in DenonStatusListener::STATUS_QUERIES {
+            if let Err(e) = telnet.send_command(query) {
+                warn!("denon status query {} failed: {}", query, e);
+                break;
+            }
+        }
+
+        while is_running.load(Ordering::SeqCst) {
+            match responses.recv_timeout(poll) {
+                Ok(response) => {
+                    let mut state = state.lock().unwrap();
+                    if state.apply(response) {
+                        notify(subscribers, &state);
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    // nothing is known until the receiver reports again
+                    let mut state = state.lock().unwrap();
+                    *state = ReceiverState::default();
+                    notify(subscribers, &state);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn notify(subscribers: &Mutex<Vec<mpsc::Sender<ReceiverState>>>, state: &ReceiverState) {
+    subscribers
+        .lock()
+        .unwrap()
+        .retain(|subscriber| subscriber.send(state.clone()).is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply_lines(state: &mut ReceiverState, lines: &[&str]) -> Vec<bool> {
+        lines
+            .iter()
+            .map(|line| state.apply(DenonResponse::parse(line)))
+            .collect()
+    }
+
+    #[test]
+    fn applies_main_zone_lines() {
+        let mut state = ReceiverState::default();
+        let changed = apply_lines(
+            &mut state,
+            &[
+                "PWON", "ZMON", "MV455", "MVMAX 98", "MUOFF", "SIGAME", "MSSTEREO",
+            ],
+        );
+        assert_eq!(changed, [true; 7]);
+        assert_eq!(
+            state,
+            ReceiverState {
+                is_powered_on: Some(true),
+                is_main_zone_on: Some(true),
+                volume: Some(Volume::from_absolute(45.5)),
+                max_volume: Some(Volume::from_absolute(98.0)),
+                is_muted: Some(false),
+                input: Some("GAME".to_string()),
+                surround_mode: Some(SurroundMode::Stereo),
+                ..ReceiverState::default()
+            }
+        );
+    }
+
+    #[test]
+    fn reports_only_changes() {
+        let mut state = ReceiverState::default();
+        assert_eq!(
+            apply_lines(&mut state, &["MV50", "MV50", "MV505", "MUON", "MUON"]),
+            [true, false, true, true, false]
+        );
+        assert_eq!(state.volume, Some(Volume::from_absolute(50.5)));
+    }
+
+    #[test]
+    fn applies_zone_lines_to_their_zone() {
+        let mut state = ReceiverState::default();
+        let changed = apply_lines(
+            &mut state,
+            &["Z2ON", "Z245", "Z2MUON", "Z2TUNER", "Z3OFF", "Z2SLP030"],
+        );
+        assert_eq!(changed, [true, true, true, true, true, false]);
+        assert_eq!(
+            state.zone2,
+            ZoneState {
+                is_on: Some(true),
+                volume: Some(Volume::from_absolute(45.0)),
+                is_muted: Some(true),
+                input: Some("TUNER".to_string()),
+            }
+        );
+        assert_eq!(
+            state.zone3,
+            ZoneState {
+                is_on: Some(false),
+                ..ZoneState::default()
+            }
+        );
+        assert_eq!(state.volume, None);
+    }
+
+    #[test]
+    fn ignores_settings_that_are_not_state() {
+        let mut state = ReceiverState::default();
+        apply_lines(&mut state, &["MSMOVIE"]);
+        let changed = apply_lines(
+            &mut state,
+            &[
+                "MSQUICK1",
+                "MSSMART1",
+                "PSBAS 50",
+                "CVFL 50",
+                "SSINFAISSIG 01",
+                "",
+            ],
+        );
+        assert_eq!(changed, [false; 6]);
+        assert_eq!(state.surround_mode, Some(SurroundMode::Movie));
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::Result;
+
+use quick_xml::de::from_str;
+use serde::Deserialize;
+
+use crate::denon_audio::SurroundMode;
+use crate::volume::Volume;
+
+/// Everything the receiver's status page reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiverStatus {
+    pub is_powered_on: bool,
+    pub main_zone: ZoneStatus,
+    pub surround_mode: Option<SurroundMode>,
+    /// The inputs that haven't been hidden, with the names they were given.
+    pub sources: Vec<ReceiverSource>,
+    /// None if the receiver has no such zone.
+    pub zone2: Option<ZoneStatus>,
+    pub zone3: Option<ZoneStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneStatus {
+    /// The zone's name as set up on the receiver, e.g. "MAIN ZONE".
+    pub name: Option<String>,
+    pub is_on: bool,
+    /// The source as the receiver names it, e.g. "GAME" or "SAT/CBL".
+    pub input: Option<String>,
+    /// None while the receiver shows "--" (e.g. in standby).
+    pub volume: Option<Volume>,
+    pub is_muted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiverSource {
+    /// The input's command name, e.g. "SAT/CBL".
+    pub input: String,
+    /// What the input was renamed to, e.g. "Cable Box".
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DenonSummaryValueItem {
+    #[serde(rename = "$value", default)]
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DenonSummaryItem {
+    #[serde(rename = "value")]
+    value: DenonSummaryValueItem,
+}
+
+impl DenonSummaryItem {
+    fn text(&self) -> &str {
+        self.value.text.trim()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DenonSummaryList {
+    #[serde(rename = "value", default)]
+    values: Vec<DenonSummaryListValue>,
+}
+
+// Some models wrap each list entry in a second value element.
+#[derive(Debug, Deserialize)]
+struct DenonSummaryListValue {
+    #[serde(rename = "$text", default)]
+    text: String,
+
+    #[serde(rename = "value")]
+    nested: Option<String>,
+}
+
+impl DenonSummaryListValue {
+    fn text(&self) -> &str {
+        self.nested.as_deref().unwrap_or(&self.text).trim()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DenonSummaryRaw {
+    #[serde(rename = "Power")]
+    power: Option<DenonSummaryItem>,
+
+    #[serde(rename = "ZonePower")]
+    zone_power: Option<DenonSummaryItem>,
+
+    #[serde(rename = "RenameZone")]
+    rename_zone: Option<DenonSummaryItem>,
+
+    #[serde(rename = "InputFuncSelect")]
+    input_func_select: Option<DenonSummaryItem>,
+
+    #[serde(rename = "selectSurround")]
+    select_surround: Option<DenonSummaryItem>,
+
+    #[serde(rename = "InputFuncList")]
+    input_func_list: Option<DenonSummaryList>,
+
+    #[serde(rename = "RenameSource")]
+    rename_source: Option<DenonSummaryList>,
+
+    #[serde(rename = "SourceDelete")]
+    source_delete: Option<DenonSummaryList>,
+
+    #[serde(rename = "Mute")]
+    mute: DenonSummaryItem,
+
+    #[serde(rename = "MasterVolume")]
+    master_volume: DenonSummaryItem,
+}
+
+/// One zone's status page, before it's known which zone it is.
+pub(crate) struct DenonZoneSummary {
+    pub is_powered_on: bool,
+    pub zone: ZoneStatus,
+    pub surround_mode: Option<SurroundMode>,
+    pub sources: Vec<ReceiverSource>,
+}
+
+impl DenonZoneSummary {
+    pub fn parse(xml: &str) -> Result<DenonZoneSummary> {
+        let raw: DenonSummaryRaw = from_str(xml)?;
+
+        let is_on = |item: &DenonSummaryItem| item.text() == "ON";
+        let is_powered_on = raw.power.as_ref().is_some_and(is_on);
+        // a zone's own page may only have Power
+        let is_zone_on = raw.zone_power.as_ref().map_or(is_powered_on, is_on);
+
+        // reported in dB whichever scale the receiver displays
+        let volume = match raw.master_volume.text() {
+            "--" => None,
+            db => Some(Volume::from_db(db.parse::<f32>()?)),
+        };
+
+        let sources = match (&raw.input_func_list, &raw.rename_source) {
+            (Some(inputs), Some(names)) => {
+                let deleted = raw.source_delete.as_ref().map_or(vec![], |deleted| {
+                    deleted.values.iter().map(|v| v.text() == "DEL").collect()
+                });
+                inputs
+                    .values
+                    .iter()
+                    .zip(names.values.iter())
+                    .enumerate()
+                    .filter(|(i, _)| !deleted.get(*i).copied().unwrap_or(false))
+                    .map(|(_, (input, name))| ReceiverSource {
+                        input: input.text().to_string(),
+                        name: name.text().to_string(),
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        };
+
+        let non_empty = |item: &Option<DenonSummaryItem>| {
+            item.as_ref()
+                .map(|item| item.text().to_string())
+                .filter(|text| !text.is_empty())
+        };
+
+        Ok(DenonZoneSummary {
+            is_powered_on,
+            zone: ZoneStatus {
+                name: non_empty(&raw.rename_zone),
+                is_on: is_zone_on,
+                input: non_empty(&raw.input_func_select),
+                volume,
+                is_muted: raw.mute.text() == "on",
+            },
+            surround_mode: non_empty(&raw.select_surround).map(|mode| SurroundMode::parse(&mode)),
+            sources,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary_xml(body: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\" ?>\n<item>{}\
+             <Mute><value>off</value></Mute>\
+             <MasterVolume><value>-30.5</value></MasterVolume></item>",
+            body
+        )
+    }
+
+    const INPUTS: &str = "<InputFuncList><value>SAT/CBL</value><value>GAME</value>\
+                          <value>TUNER</value></InputFuncList>";
+
+    #[test]
+    fn parses_the_main_zone() {
+        let summary = DenonZoneSummary::parse(&summary_xml(
+            "<Power><value>ON</value></Power>\
+             <ZonePower><value>ON</value></ZonePower>\
+             <RenameZone><value>MAIN ZONE </value></RenameZone>\
+             <InputFuncSelect><value>GAME</value></InputFuncSelect>\
+             <selectSurround><value>STEREO</value></selectSurround>",
+        ))
+        .unwrap();
+
+        assert!(summary.is_powered_on);
+        assert_eq!(
+            summary.zone,
+            ZoneStatus {
+                name: Some("MAIN ZONE".to_string()),
+                is_on: true,
+                input: Some("GAME".to_string()),
+                volume: Some(Volume::from_db(-30.5)),
+                is_muted: false,
+            }
+        );
+        assert_eq!(summary.surround_mode, Some(SurroundMode::Stereo));
+        assert_eq!(summary.sources, []);
+    }
+
+    #[test]
+    fn parses_flat_and_nested_source_names() {
+        let expected = vec![
+            ReceiverSource {
+                input: "SAT/CBL".to_string(),
+                name: "Cable Box".to_string(),
+            },
+            ReceiverSource {
+                input: "GAME".to_string(),
+                name: "PS5".to_string(),
+            },
+            ReceiverSource {
+                input: "TUNER".to_string(),
+                name: "TUNER".to_string(),
+            },
+        ];
+
+        let flat = DenonZoneSummary::parse(&summary_xml(&format!(
+            "{}<RenameSource><value>Cable Box</value><value>PS5 </value>\
+             <value>TUNER</value></RenameSource>",
+            INPUTS
+        )))
+        .unwrap();
+        assert_eq!(flat.sources, expected);
+
+        let nested = DenonZoneSummary::parse(&summary_xml(&format!(
+            "{}<RenameSource><value><value>Cable Box</value></value>\
+             <value><value>PS5</value></value><value><value>TUNER</value></value>\
+             </RenameSource>",
+            INPUTS
+        )))
+        .unwrap();
+        assert_eq!(nested.sources, expected);
+    }
+
+    #[test]
+    fn leaves_out_deleted_sources() {
+        let summary = DenonZoneSummary::parse(&summary_xml(&format!(
+            "{}<RenameSource><value>Cable Box</value><value>PS5</value>\
+             <value>TUNER</value></RenameSource>\
+             <SourceDelete><value>USE</value><value>DEL</value><value>USE</value>\
+             </SourceDelete>",
+            INPUTS
+        )))
+        .unwrap();
+        let inputs: Vec<&str> = summary
+            .sources
+            .iter()
+            .map(|source| source.input.as_str())
+            .collect();
+        assert_eq!(inputs, ["SAT/CBL", "TUNER"]);
+    }
+
+    #[test]
+    fn has_no_volume_in_standby() {
+        let xml = "<item><Power><value>STANDBY</value></Power>\
+                   <Mute><value>on</value></Mute>\
+                   <MasterVolume><value>--</value></MasterVolume></item>";
+        let summary = DenonZoneSummary::parse(xml).unwrap();
+        assert!(!summary.is_powered_on);
+        assert!(!summary.zone.is_on);
+        assert_eq!(summary.zone.volume, None);
+        assert!(summary.zone.is_muted);
+        assert_eq!(summary.zone.name, None);
+        assert_eq!(summary.surround_mode, None);
+    }
+
+    #[test]
+    fn zone_power_falls_back_to_power() {
+        // a zone's own page may only have Power
+        let summary =
+            DenonZoneSummary::parse(&summary_xml("<Power><value>ON</value></Power>")).unwrap();
+        assert!(summary.zone.is_on);
+
+        let summary = DenonZoneSummary::parse(&summary_xml(
+            "<Power><value>ON</value></Power><ZonePower><value>OFF</value></ZonePower>",
+        ))
+        .unwrap();
+        assert!(summary.is_powered_on);
+        assert!(!summary.zone.is_on);
+    }
+
+    #[test]
+    fn rejects_a_bad_volume() {
+        let xml = "<item><Mute><value>off</value></Mute>\
+                   <MasterVolume><value>loud</value></MasterVolume></item>";
+        assert!(DenonZoneSummary::parse(xml).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::io::prelude::*;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::{thread, time};
+
+use crate::denon_audio::{parse_channel_level, AudioSetting, Channel, SurroundMode};
+use crate::denon_zone::Zone;
+use crate::volume::Volume;
+
+// Protocol details found here:
+// https://assets.denon.com/documentmaster/us/denon%20master%20protocol.pdf
+
+/// One status line from the receiver, e.g. "PWON" or "MV455".
+#[derive(Debug, Clone, PartialEq)]
+pub enum DenonResponse {
+    Power(bool),
+    MainZonePower(bool),
+    Volume(Volume),
+    MaxVolume(Volume),
+    Mute(bool),
+    Input(String),
+    SurroundMode(SurroundMode),
+    /// A "PS" line, e.g. "PSBAS 50".
+    Audio(AudioSetting),
+    /// A "CV" line, e.g. "CVFL 50".  In dB.
+    ChannelLevel(Channel, f32),
+    /// A line about zone 2 or 3.
+    Zone(Zone, ZoneResponse),
+    Other(String),
+}
+
+/// One status line from zone 2 or 3, e.g. "Z2ON" or "Z2GAME".
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZoneResponse {
+    Power(bool),
+    Volume(Volume),
+    Mute(bool),
+    Input(String),
+    Other(String),
+}
+
+impl ZoneResponse {
+    // Zone settings that share the prefix but aren't a source name.
+    const SETTING_PREFIXES: [&'static str; 9] = [
+        "CV", "PS", "SLP", "QUICK", "SMART", "HPF", "HDA", "STBY", "CS",
+    ];
+
+    fn parse(value: &str) -> ZoneResponse {
+        if let Some(on) = parse_on_off(value, "OFF") {
+            ZoneResponse::Power(on)
+        } else if let Some(volume) = parse_volume(value) {
+            ZoneResponse::Volume(Volume::from_absolute(volume))
+        } else if let Some(on) = value
+            .strip_prefix("MU")
+            .and_then(|v| parse_on_off(v, "OFF"))
+        {
+            ZoneResponse::Mute(on)
+        } else if value.is_empty()
+            || Self::SETTING_PREFIXES
+                .iter()
+                .any(|prefix| value.starts_with(prefix))
+        {
+            ZoneResponse::Other(value.to_string())
+        } else {
+            ZoneResponse::Input(value.to_string())
+        }
+    }
+}
+
+impl DenonResponse {
+    // Quick and smart select memories share the "MS" prefix but aren't a
+    // surround mode, e.g. "MSQUICK1".
+    const SURROUND_SETTING_PREFIXES: [&'static str; 2] = ["QUICK", "SMART"];
+
+    pub fn parse(line: &str) -> DenonResponse {
+        let line = line.trim();
+        let parsed = if let Some(value) = line.strip_prefix("Z2") {
+            Some(DenonResponse::Zone(Zone::Zone2, ZoneResponse::parse(value)))
+        } else if let Some(value) = line.strip_prefix("Z3") {
+            Some(DenonResponse::Zone(Zone::Zone3, ZoneResponse::parse(value)))
+        } else if let Some(value) = line.strip_prefix("PW") {
+            parse_on_off(value, "STANDBY").map(DenonResponse::Power)
+        } else if let Some(value) = line.strip_prefix("ZM") {
+            parse_on_off(value, "OFF").map(DenonResponse::MainZonePower)
+        } else if let Some(value) = line.strip_prefix("MVMAX") {
+            parse_volume(value.trim()).map(|v| DenonResponse::MaxVolume(Volume::from_absolute(v)))
+        } else if let Some(value) = line.strip_prefix("MV") {
+            parse_volume(value).map(|v| DenonResponse::Volume(Volume::from_absolute(v)))
+        } else if let Some(value) = line.strip_prefix("MU") {
+            parse_on_off(value, "OFF").map(DenonResponse::Mute)
+        } else if let Some(value) = line.strip_prefix("MS").filter(|value| {
+            !Self::SURROUND_SETTING_PREFIXES
+                .iter()
+                .any(|prefix| value.starts_with(prefix))
+        }) {
+            Some(DenonResponse::SurroundMode(SurroundMode::parse(value)))
+        } else if let Some(value) = line.strip_prefix("PS") {
+            Some(DenonResponse::Audio(AudioSetting::parse(value)))
+        } else if let Some(value) = line.strip_prefix("CV") {
+            parse_channel_level(value).map(|(channel, db)| DenonResponse::ChannelLevel(channel, db))
+        } else {
+            line.strip_prefix("SI")
+                .map(|value| DenonResponse::Input(value.to_string()))
+        };
+        parsed.unwrap_or_else(|| DenonResponse::Other(line.to_string()))
+    }
+}
+
+fn parse_on_off(value: &str, off: &str) -> Option<bool> {
+    match value {
+        "ON" => Some(true),
+        v if v == off => Some(false),
+        _ => None,
+    }
+}
+
+// Two digits are whole steps, a third digit is a half step ("455" is 45.5).
+pub(crate) fn parse_volume(value: &str) -> Option<f32> {
+    if !value.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    match value.len() {
+        2 => value.parse::<f32>().ok(),
+        3 => value.parse::<f32>().ok().map(|v| v / 10.0),
+        _ => None,
+    }
+}
+
+/// The line based control protocol Denon and Marantz receivers speak on TCP
+/// port 23.  Unlike the HTTP endpoint it answers every command with the new
+/// state, and reports changes made from the remote or front panel as they
+/// happen.
+///
+/// The receiver only accepts one connection at a time, so the connection is
+/// kept open between commands and reopened if it drops.  A reader thread
+/// hands every line it receives to the current subscribers.
+pub struct DenonTelnet {
+    device_address: String,
+    connection: Mutex<Option<DenonConnection>>,
+}
+
+struct DenonConnection {
+    stream: TcpStream,
+    is_open: Arc<AtomicBool>,
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<DenonResponse>>>>,
+}
+
+impl DenonConnection {
+    fn subscribe(&self) -> mpsc::Receiver<DenonResponse> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+impl DenonTelnet {
+    const PORT: i32 = 23;
+    const CONNECT_TIMEOUT_MS: u64 = 2000;
+    // The receiver answers within 200ms, and wants 50ms between commands.
+    const RESPONSE_TIMEOUT_MS: u64 = 200;
+    const COMMAND_INTERVAL_MS: u64 = 50;
+
+    pub fn new(device_address: &str) -> DenonTelnet {
+        DenonTelnet {
+            device_address: device_address.to_string(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// Sends a command (e.g. "PWON" or "MV?") and returns whatever the
+    /// receiver reports back before it goes quiet.
+    pub fn send_command(&self, command: &str) -> Result<Vec<DenonResponse>> {
+        let mut connection = self.connection.lock().unwrap();
+
+        // a receiver that was power cycled drops the connection, so retry once.
+        // Only while sending: a command that went out isn't repeated, since
+        // e.g. "MVUP" would then step twice.
+        let responses = match self.write_command(&mut connection, command) {
+            Ok(responses) => responses,
+            Err(e) => {
+                warn!(
+                    "denon telnet command {} failed, reconnecting: {}",
+                    command, e
+                );
+                disconnect(&mut connection);
+                self.write_command(&mut connection, command)?
+            }
+        };
+
+        Self::collect_responses(&responses)
+    }
+
+    /// Every line the receiver sends from now on, whether it answers a
+    /// command or not.  The channel closes when the connection drops.
+    pub fn subscribe(&self) -> Result<mpsc::Receiver<DenonResponse>> {
+        let mut connection = self.connection.lock().unwrap();
+        Ok(self.ensure_connected(&mut connection)?.subscribe())
+    }
+
+    fn get_url(&self) -> String {
+        format!("{}:{}", self.device_address, Self::PORT)
+    }
+
+    fn ensure_connected<'a>(
+        &self,
+        connection: &'a mut Option<DenonConnection>,
+    ) -> Result<&'a DenonConnection> {
+        if connection
+            .as_ref()
+            .is_some_and(|c| !c.is_open.load(Ordering::SeqCst))
+        {
+            disconnect(connection);
+        }
+
+        if connection.is_none() {
+            let address = std::net::ToSocketAddrs::to_socket_addrs(&self.get_url())?
+                .next()
+                .ok_or_else(|| anyhow!("could not resolve {}", self.device_address))?;
+            let stream = TcpStream::connect_timeout(
+                &address,
+                time::Duration::from_millis(Self::CONNECT_TIMEOUT_MS),
+            )?;
+
+            let reader = stream.try_clone()?;
+            let is_open = Arc::new(AtomicBool::new(true));
+            let subscribers = Arc::new(Mutex::new(vec![]));
+            let reader_is_open = is_open.clone();
+            let reader_subscribers = subscribers.clone();
+            thread::spawn(move || read_responses(reader, &reader_is_open, &reader_subscribers));
+
+            *connection = Some(DenonConnection {
+                stream,
+                is_open,
+                subscribers,
+            });
+        }
+        Ok(connection.as_ref().unwrap())
+    }
+
+    // Subscribes before writing, so no part of the answer is missed.
+    fn write_command(
+        &self,
+        connection: &mut Option<DenonConnection>,
+        command: &str,
+    ) -> Result<mpsc::Receiver<DenonResponse>> {
+        let connection = self.ensure_connected(connection)?;
+        let responses = connection.subscribe();
+        (&connection.stream).write_all(format!("{}\r", command).as_bytes())?;
+        Ok(responses)
+    }
+
+    fn collect_responses(responses: &mpsc::Receiver<DenonResponse>) -> Result<Vec<DenonResponse>> {
+        let mut result = vec![];
+        loop {
+            match responses.recv_timeout(time::Duration::from_millis(Self::RESPONSE_TIMEOUT_MS)) {
+                Ok(response) => result.push(response),
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow!("denon receiver closed the connection"))
+                }
+            }
+        }
+        thread::sleep(time::Duration::from_millis(Self::COMMAND_INTERVAL_MS));
+
+        Ok(result)
+    }
+}
+
+impl Drop for DenonTelnet {
+    fn drop(&mut self) {
+        disconnect(&mut self.connection.lock().unwrap());
+    }
+}
+
+fn disconnect(connection: &mut Option<DenonConnection>) {
+    if let Some(connection) = connection.take() {
+        // wakes the reader thread so it can exit
+        let _ = connection.stream.shutdown(Shutdown::Both);
+    }
+}
+
+fn read_responses(
+    mut stream: TcpStream,
+    is_open: &AtomicBool,
+    subscribers: &Mutex<Vec<mpsc::Sender<DenonResponse>>>,
+) {
+    let mut pending: Vec<u8> = vec![];
+    let mut buf = [0u8; 256];
+    while let Ok(len @ 1..) = stream.read(&mut buf) {
+        pending.extend_from_slice(&buf[0..len]);
+
+        while let Some(end) = pending.iter().position(|&b| b == b'\r') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]);
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = DenonResponse::parse(&line);
+            subscribers
+                .lock()
+                .unwrap()
+                .retain(|subscriber| subscriber.send(response.clone()).is_ok());
+        }
+    }
+
+    is_open.store(false, Ordering::SeqCst);
+    // closes the subscribers' channels
+    subscribers.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_status_lines() {
+        let cases = [
+            ("MV455", DenonResponse::Volume(Volume::from_absolute(45.5))),
+            ("MV50", DenonResponse::Volume(Volume::from_absolute(50.0))),
+            (
+                "MVMAX 98",
+                DenonResponse::MaxVolume(Volume::from_absolute(98.0)),
+            ),
+            ("PWON", DenonResponse::Power(true)),
+            ("PWSTANDBY", DenonResponse::Power(false)),
+            ("ZMOFF", DenonResponse::MainZonePower(false)),
+            ("MUON\r", DenonResponse::Mute(true)),
+            ("SIGAME", DenonResponse::Input("GAME".to_string())),
+            (
+                "Z2MUON",
+                DenonResponse::Zone(Zone::Zone2, ZoneResponse::Mute(true)),
+            ),
+            (
+                "Z250",
+                DenonResponse::Zone(
+                    Zone::Zone2,
+                    ZoneResponse::Volume(Volume::from_absolute(50.0)),
+                ),
+            ),
+            (
+                "Z2SLP030",
+                DenonResponse::Zone(Zone::Zone2, ZoneResponse::Other("SLP030".to_string())),
+            ),
+            (
+                "Z3OFF",
+                DenonResponse::Zone(Zone::Zone3, ZoneResponse::Power(false)),
+            ),
+            (
+                "Z3SAT/CBL",
+                DenonResponse::Zone(Zone::Zone3, ZoneResponse::Input("SAT/CBL".to_string())),
+            ),
+            (
+                "MSDOLBY ATMOS",
+                DenonResponse::SurroundMode(SurroundMode::Dolby("DOLBY ATMOS".to_string())),
+            ),
+            ("MSQUICK1", DenonResponse::Other("MSQUICK1".to_string())),
+            ("MSSMART2", DenonResponse::Other("MSSMART2".to_string())),
+            ("PWMAYBE", DenonResponse::Other("PWMAYBE".to_string())),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(DenonResponse::parse(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn parses_zone_lines() {
+        let cases = [
+            ("ON", ZoneResponse::Power(true)),
+            ("455", ZoneResponse::Volume(Volume::from_absolute(45.5))),
+            ("MUOFF", ZoneResponse::Mute(false)),
+            ("", ZoneResponse::Other(String::new())),
+            ("CVFL 50", ZoneResponse::Other("CVFL 50".to_string())),
+            ("TUNER", ZoneResponse::Input("TUNER".to_string())),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(ZoneResponse::parse(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn parses_volumes() {
+        let cases = [
+            ("00", Some(0.0)),
+            ("05", Some(5.0)),
+            ("455", Some(45.5)),
+            ("005", Some(0.5)),
+            ("98", Some(98.0)),
+            ("5", None),
+            ("4555", None),
+            ("MAX 98", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_volume(value), expected, "{}", value);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::{anyhow, Result};
+
+use crate::denon::{receiver_input_to_command_string, DenonReceiver, ReceiverInput};
+use crate::denon_status::ZoneStatus;
+use crate::denon_telnet::{DenonResponse, ZoneResponse};
+use crate::volume::Volume;
+use log::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    Main,
+    Zone2,
+    Zone3,
+}
+
+impl Zone {
+    // Zones 2 and 3 use one prefix for everything, e.g. "Z2ON", "Z245" and "Z2GAME".
+    fn prefix(self, main_zone_prefix: &'static str) -> &'static str {
+        match self {
+            Zone::Main => main_zone_prefix,
+            Zone::Zone2 => "Z2",
+            Zone::Zone3 => "Z3",
+        }
+    }
+
+    fn power_command(self, on: bool) -> String {
+        format!("{}{}", self.prefix("ZM"), if on { "ON" } else { "OFF" })
+    }
+
+    fn volume_command(self) -> &'static str {
+        self.prefix("MV")
+    }
+
+    fn mute_command(self) -> String {
+        format!("{}MU", self.prefix(""))
+    }
+
+    fn input_command(self) -> &'static str {
+        self.prefix("SI")
+    }
+
+    pub(crate) fn status_page(self) -> &'static str {
+        match self {
+            Zone::Main => "formMainZone_MainZoneXml.xml",
+            Zone::Zone2 => "formZone2_Zone2XmlStatus.xml",
+            Zone::Zone3 => "formZone3_Zone3XmlStatus.xml",
+        }
+    }
+
+    fn volume_of(self, response: DenonResponse) -> Option<Volume> {
+        match (self, response) {
+            (Zone::Main, DenonResponse::Volume(volume)) => Some(volume),
+            (zone, DenonResponse::Zone(response_zone, ZoneResponse::Volume(volume)))
+                if zone == response_zone =>
+            {
+                Some(volume)
+            }
+            _ => None,
+        }
+    }
+
+    fn mute_of(self, response: DenonResponse) -> Option<bool> {
+        match (self, response) {
+            (Zone::Main, DenonResponse::Mute(is_muted)) => Some(is_muted),
+            (zone, DenonResponse::Zone(response_zone, ZoneResponse::Mute(is_muted)))
+                if zone == response_zone =>
+            {
+                Some(is_muted)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Controls one zone of a receiver.  Get one with `DenonReceiver::zone`.
+pub struct DenonZone<'a> {
+    receiver: &'a DenonReceiver,
+    zone: Zone,
+}
+
+impl<'a> DenonZone<'a> {
+    pub(crate) fn new(receiver: &'a DenonReceiver, zone: Zone) -> DenonZone<'a> {
+        DenonZone { receiver, zone }
+    }
+
+    pub fn get_zone(&self) -> Zone {
+        self.zone
+    }
+
+    /// Turning on a zone also wakes the receiver.
+    pub fn turn_on(&self) -> Result<()> {
+        self.receiver.send_command(&self.zone.power_command(true))
+    }
+
+    /// Leaves the receiver and the other zones running.
+    pub fn turn_off(&self) -> Result<()> {
+        self.receiver.send_command(&self.zone.power_command(false))
+    }
+
+    /// Capped at the receiver's `get_max_volume`.
+    pub fn set_volume(&self, volume: Volume) -> Result<()> {
+        let max_volume = self.receiver.get_max_volume();
+        let volume = if volume > max_volume {
+            warn!(
+                "capping {:?} volume {} at the maximum of {}",
+                self.zone, volume, max_volume
+            );
+            max_volume
+        } else {
+            volume
+        };
+
+        self.receiver.send_command(&format!(
+            "{}{}",
+            self.zone.volume_command(),
+            volume.to_command_string()
+        ))
+    }
+
+    /// None while the receiver has no volume to report, e.g. in standby.
+    pub fn get_volume(&self) -> Result<Option<Volume>> {
+        match self.receiver.get_telnet() {
+            None => Ok(self.get_status()?.volume),
+            Some(telnet) => Ok(telnet
+                .send_command(&format!("{}?", self.zone.volume_command()))?
+                .into_iter()
+                .find_map(|response| self.zone.volume_of(response))),
+        }
+    }
+
+    /// One step up, as on the remote.  Does nothing once at the receiver's
+    /// `get_max_volume`.
+    pub fn volume_up(&self) -> Result<()> {
+        let max_volume = self.receiver.get_max_volume();
+        if self
+            .get_volume()?
+            .is_some_and(|volume| volume >= max_volume)
+        {
+            return Ok(());
+        }
+
+        let command = format!("{}UP", self.zone.volume_command());
+        match self.receiver.get_telnet() {
+            None => self.receiver.send_command(&command),
+            Some(telnet) => {
+                // the step size is a receiver setting, so check where it landed
+                let volume = telnet
+                    .send_command(&command)?
+                    .into_iter()
+                    .find_map(|response| self.zone.volume_of(response));
+                match volume {
+                    Some(volume) if volume > max_volume => self.set_volume(max_volume),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// One step down, as on the remote.
+    pub fn volume_down(&self) -> Result<()> {
+        self.receiver
+            .send_command(&format!("{}DOWN", self.zone.volume_command()))
+    }
+
+    pub fn mute(&self, mute: bool) -> Result<()> {
+        self.receiver.send_command(&format!(
+            "{}{}",
+            self.zone.mute_command(),
+            if mute { "ON" } else { "OFF" }
+        ))
+    }
+
+    pub fn is_muted(&self) -> Result<bool> {
+        match self.receiver.get_telnet() {
+            None => Ok(self.get_status()?.is_muted),
+            Some(telnet) => telnet
+                .send_command(&format!("{}?", self.zone.mute_command()))?
+                .into_iter()
+                .find_map(|response| self.zone.mute_of(response))
+                .ok_or_else(|| anyhow!("receiver did not report the {:?} mute state", self.zone)),
+        }
+    }
+
+    pub fn select_input(&self, input: ReceiverInput) -> Result<()> {
+        self.receiver.send_command(&format!(
+            "{}{}",
+            self.zone.input_command(),
+            receiver_input_to_command_string(input)
+        ))
+    }
+
+    /// Always uses HTTP, like `DenonReceiver::get_status`.
+    pub fn get_status(&self) -> Result<ZoneStatus> {
+        Ok(self.receiver.load_summary(self.zone)?.zone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_zone_commands() {
+        assert_eq!(Zone::Main.power_command(true), "ZMON");
+        assert_eq!(Zone::Main.power_command(false), "ZMOFF");
+        assert_eq!(Zone::Zone2.power_command(false), "Z2OFF");
+        assert_eq!(Zone::Main.volume_command(), "MV");
+        assert_eq!(Zone::Zone3.volume_command(), "Z3");
+        assert_eq!(Zone::Main.mute_command(), "MU");
+        assert_eq!(Zone::Zone2.mute_command(), "Z2MU");
+        assert_eq!(Zone::Main.input_command(), "SI");
+    }
+}
@@ -39,12 +39,12 @@ impl EpsonProjector {
         ];
 
         let mut stream = TcpStream::connect(self.get_url())?;
-        stream.write(&init_bytes)?;
+        stream.write_all(&init_bytes)?;
 
         thread::sleep(time::Duration::from_millis(100));
         let final_command = format!("{}\r", command).to_ascii_uppercase();
 
-        stream.write(&final_command.as_bytes())?;
+        stream.write_all(final_command.as_bytes())?;
 
         Ok(())
     }
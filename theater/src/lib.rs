@@ -1,16 +1,32 @@
+use std::sync::mpsc;
 use std::{thread, time};
 
 use anyhow::Result;
 mod denon;
+mod denon_audio;
+mod denon_listener;
+mod denon_status;
+mod denon_telnet;
+mod denon_zone;
 mod epson;
+mod volume;
 use log::error;
 
-pub use self::denon::{DenonReceiver, ReceiverInput};
+pub use self::denon::{DenonProtocol, DenonReceiver, ReceiverInput};
+pub use self::denon_audio::{
+    AudioSetting, AudioSettings, Channel, DialogEnhancer, MultEq, SurroundMode,
+};
+pub use self::denon_listener::{DenonStatusListener, ReceiverState, ZoneState};
+pub use self::denon_status::{ReceiverSource, ReceiverStatus, ZoneStatus};
+pub use self::denon_telnet::{DenonResponse, DenonTelnet, ZoneResponse};
+pub use self::denon_zone::{DenonZone, Zone};
 pub use self::epson::EpsonProjector;
+pub use self::volume::Volume;
 
 pub struct Theater {
     receiver: DenonReceiver,
     projector: EpsonProjector,
+    receiver_status: Option<DenonStatusListener>,
 }
 
 impl Theater {
@@ -18,9 +34,38 @@ impl Theater {
         Theater {
             projector: EpsonProjector::new(epson_address),
             receiver: DenonReceiver::new(denon_address),
+            receiver_status: None,
         }
     }
 
+    pub fn with_devices(receiver: DenonReceiver, projector: EpsonProjector) -> Theater {
+        Theater {
+            receiver,
+            projector,
+            receiver_status: None,
+        }
+    }
+
+    /// Follows the receiver's status as it changes, so volume and mute come
+    /// from what the receiver last reported instead of a fresh request.
+    pub fn start_receiver_status_listener(&mut self) {
+        self.receiver_status = Some(self.receiver.start_status_listener());
+    }
+
+    /// None unless the status listener is running.
+    pub fn get_receiver_state(&self) -> Option<ReceiverState> {
+        self.receiver_status
+            .as_ref()
+            .map(|listener| listener.get_state())
+    }
+
+    /// None unless the status listener is running.
+    pub fn subscribe_receiver_state(&self) -> Option<mpsc::Receiver<ReceiverState>> {
+        self.receiver_status
+            .as_ref()
+            .map(|listener| listener.subscribe())
+    }
+
     pub fn turn_on(&self, input: ReceiverInput) {
         let do_steps = || -> Result<()> {
             self.receiver.turn_on()?;
@@ -49,25 +94,47 @@ impl Theater {
         }
     }
 
-    pub fn set_volume(&self, volume: i32) {
+    pub fn set_volume(&self, volume: Volume) {
         match self.receiver.set_volume(volume) {
             Ok(()) => {}
             Err(e) => error!("{}", e),
         }
     }
 
-    pub fn get_volume(&self) -> i32 {
+    /// The quietest volume if the receiver can't say.
+    pub fn get_volume(&self) -> Volume {
+        if let Some(volume) = self.get_receiver_state().and_then(|state| state.volume) {
+            return volume;
+        }
         match self.receiver.get_volume() {
-            Ok(x) => x,
+            Ok(x) => x.unwrap_or(Volume::MIN),
             Err(e) => {
                 error!("{}", e);
-                0
+                Volume::MIN
             }
         }
     }
 
+    pub fn volume_up(&self) {
+        match self.receiver.volume_up() {
+            Ok(()) => {}
+            Err(e) => error!("{}", e),
+        }
+    }
+
+    pub fn volume_down(&self) {
+        match self.receiver.volume_down() {
+            Ok(()) => {}
+            Err(e) => error!("{}", e),
+        }
+    }
+
     pub fn toggle_mute(&self) {
-        match self.receiver.is_muted() {
+        let is_muted = match self.get_receiver_state().and_then(|state| state.is_muted) {
+            Some(is_muted) => Ok(is_muted),
+            None => self.receiver.is_muted(),
+        };
+        match is_muted {
             Ok(is_muted) => match self.receiver.mute(!is_muted) {
                 Ok(()) => {}
                 Err(e) => error!("{}", e),
@@ -75,4 +142,18 @@ impl Theater {
             Err(e) => error!("{}", e),
         }
     }
+
+    pub fn set_surround_mode(&self, mode: SurroundMode) {
+        match self.receiver.set_surround_mode(&mode) {
+            Ok(()) => {}
+            Err(e) => error!("{}", e),
+        }
+    }
+
+    pub fn set_dialog_enhancer(&self, level: DialogEnhancer) {
+        match self.receiver.set_dialog_enhancer(level) {
+            Ok(()) => {}
+            Err(e) => error!("{}", e),
+        }
+    }
 }
@@ -0,0 +1,112 @@
+use std::fmt;
+
+/// A receiver volume.
+///
+/// Denon receivers show volume either on an absolute 0 - 98 scale or in dB
+/// relative to reference level, which is 80 on the absolute scale.  Both
+/// move in half steps, which is how the volume is kept here.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Volume {
+    half_steps: i32,
+}
+
+impl Volume {
+    pub const MIN: Volume = Volume { half_steps: 0 };
+    pub const MAX: Volume = Volume { half_steps: 196 };
+    const REFERENCE_LEVEL: f32 = 80.0;
+
+    /// Rounds to the nearest half step within 0 - 98.
+    pub fn from_absolute(volume: f32) -> Volume {
+        let half_steps = (volume * 2.0).round() as i32;
+        Volume {
+            half_steps: num::clamp(half_steps, Self::MIN.half_steps, Self::MAX.half_steps),
+        }
+    }
+
+    pub fn to_absolute(self) -> f32 {
+        self.half_steps as f32 / 2.0
+    }
+
+    /// From dB relative to reference level, e.g. -30.5.
+    pub fn from_db(db: f32) -> Volume {
+        Volume::from_absolute(db + Self::REFERENCE_LEVEL)
+    }
+
+    pub fn to_db(self) -> f32 {
+        self.to_absolute() - Self::REFERENCE_LEVEL
+    }
+
+    /// Louder (or quieter, for a negative `db`) by `db`, kept within 0 - 98.
+    pub fn add_db(self, db: f32) -> Volume {
+        Volume::from_absolute(self.to_absolute() + db)
+    }
+
+    /// The form "MV" and "Z2" commands take, e.g. "45" or "455" for 45.5.
+    pub(crate) fn to_command_string(self) -> String {
+        if self.half_steps % 2 == 0 {
+            format!("{:02}", self.half_steps / 2)
+        } else {
+            format!("{:02}5", self.half_steps / 2)
+        }
+    }
+}
+
+impl fmt::Display for Volume {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1}", self.to_absolute())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_to_half_steps() {
+        assert_eq!(Volume::from_absolute(45.5).to_absolute(), 45.5);
+        assert_eq!(Volume::from_absolute(45.3).to_absolute(), 45.5);
+        assert_eq!(Volume::from_absolute(45.2).to_absolute(), 45.0);
+        assert_eq!(Volume::from_absolute(45.8).to_absolute(), 46.0);
+    }
+
+    #[test]
+    fn clamps_to_the_scale() {
+        assert_eq!(Volume::from_absolute(-3.0), Volume::MIN);
+        assert_eq!(Volume::from_absolute(0.0).to_absolute(), 0.0);
+        assert_eq!(Volume::from_absolute(98.0).to_absolute(), 98.0);
+        assert_eq!(Volume::from_absolute(120.0), Volume::MAX);
+        assert_eq!(Volume::MAX.to_absolute(), 98.0);
+        assert_eq!(Volume::from_db(25.0), Volume::MAX);
+        assert_eq!(Volume::from_absolute(97.0).add_db(5.0), Volume::MAX);
+        assert_eq!(Volume::from_absolute(2.0).add_db(-5.0), Volume::MIN);
+    }
+
+    #[test]
+    fn converts_to_db() {
+        assert_eq!(Volume::from_absolute(80.0).to_db(), 0.0);
+        assert_eq!(Volume::from_absolute(45.5).to_db(), -34.5);
+        assert_eq!(Volume::from_db(-30.5).to_absolute(), 49.5);
+        assert_eq!(Volume::from_db(-34.5), Volume::from_absolute(45.5));
+        assert_eq!(Volume::from_absolute(40.0).add_db(2.5).to_absolute(), 42.5);
+    }
+
+    #[test]
+    fn formats_commands() {
+        let cases = [
+            (0.0, "00"),
+            (0.5, "005"),
+            (5.0, "05"),
+            (45.0, "45"),
+            (45.5, "455"),
+            (98.0, "98"),
+        ];
+        for (volume, expected) in cases {
+            assert_eq!(
+                Volume::from_absolute(volume).to_command_string(),
+                expected,
+                "{}",
+                volume
+            );
+        }
+    }
+}